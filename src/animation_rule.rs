@@ -0,0 +1,227 @@
+use std::{
+    fmt,
+    iter::Peekable,
+    str::Chars,
+};
+
+/// Boolean expression over named flags, e.g. `!attacking && (running || jumping)`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rule {
+    Constant(bool),
+    Flag(String),
+    Not(Box<Rule>),
+    And(Box<Rule>, Box<Rule>),
+    Or(Box<Rule>, Box<Rule>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleError {
+    pub rule: String,
+    pub reason: String,
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid rule \"{}\": {}", self.rule, self.reason)
+    }
+}
+
+impl std::error::Error for RuleError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Not,
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+impl Rule {
+    /// Parses `source`, accepting only flags listed in `flags`.
+    pub fn parse(source: &str, flags: &[&str]) -> Result<Self, RuleError> {
+        let error = |reason: String| RuleError { rule: source.to_owned(), reason };
+
+        let tokens = tokenize(&mut source.chars().peekable()).map_err(error)?;
+        let mut parser = Parser { tokens, position: 0, flags };
+        let rule = parser.or().map_err(error)?;
+
+        match parser.tokens.get(parser.position) {
+            Some(token) => Err(error(format!("unexpected {:?}", token))),
+            None => Ok(rule),
+        }
+    }
+
+    pub fn evaluate<F: Fn(&str) -> bool>(&self, flag: &F) -> bool {
+        match self {
+            Rule::Constant(value) => *value,
+            Rule::Flag(name) => flag(name),
+            Rule::Not(rule) => !rule.evaluate(flag),
+            Rule::And(lhs, rhs) => lhs.evaluate(flag) && rhs.evaluate(flag),
+            Rule::Or(lhs, rhs) => lhs.evaluate(flag) || rhs.evaluate(flag),
+        }
+    }
+}
+
+fn tokenize(chars: &mut Peekable<Chars>) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '!' => {
+                chars.next();
+                tokens.push(Token::Not);
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '&' | '|' => {
+                chars.next();
+                if chars.next() != Some(c) {
+                    return Err(format!("expected \"{}{}\"", c, c));
+                }
+                tokens.push(if c == '&' { Token::And } else { Token::Or });
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut ident = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    ident.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Ident(ident));
+            }
+            c => return Err(format!("unexpected character '{}'", c)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    flags: &'a [&'a str],
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn or(&mut self) -> Result<Rule, String> {
+        let mut rule = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            rule = Rule::Or(Box::new(rule), Box::new(self.and()?));
+        }
+        Ok(rule)
+    }
+
+    fn and(&mut self) -> Result<Rule, String> {
+        let mut rule = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            rule = Rule::And(Box::new(rule), Box::new(self.unary()?));
+        }
+        Ok(rule)
+    }
+
+    fn unary(&mut self) -> Result<Rule, String> {
+        match self.next() {
+            Some(Token::Not) => Ok(Rule::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let rule = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(rule),
+                    _ => Err("expected \")\"".to_owned()),
+                }
+            }
+            Some(Token::Ident(ident)) => match ident.as_str() {
+                "true" => Ok(Rule::Constant(true)),
+                "false" => Ok(Rule::Constant(false)),
+                name if self.flags.contains(&name) => Ok(Rule::Flag(ident)),
+                name => Err(format!("unknown flag \"{}\", expected one of {:?}", name, self.flags)),
+            },
+            Some(token) => Err(format!("unexpected {:?}", token)),
+            None => Err("unexpected end of rule".to_owned()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FLAGS: &[&str] = &["running", "jumping", "attacking"];
+
+    fn parse(source: &str) -> Result<Rule, RuleError> {
+        Rule::parse(source, FLAGS)
+    }
+
+    fn flag(name: &str) -> Box<Rule> {
+        Box::new(Rule::Flag(name.to_owned()))
+    }
+
+    /// Evaluates `source` with only `set` flags raised.
+    fn evaluate(source: &str, set: &[&str]) -> bool {
+        parse(source).unwrap().evaluate(&|name| set.contains(&name))
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and_which_binds_tighter_than_or() {
+        assert_eq!(parse("!running && jumping || attacking").unwrap(),
+                   Rule::Or(Box::new(Rule::And(Box::new(Rule::Not(flag("running"))), flag("jumping"))),
+                            flag("attacking")));
+
+        assert!(evaluate("running || jumping && attacking", &["running"]));
+        assert!(!evaluate("!running && jumping", &["running", "jumping"]));
+    }
+
+    #[test]
+    fn parentheses_group() {
+        assert_eq!(parse("!(running || jumping)").unwrap(),
+                   Rule::Not(Box::new(Rule::Or(flag("running"), flag("jumping")))));
+
+        assert!(!evaluate("(running || jumping) && attacking", &["running"]));
+        assert!(evaluate("true && (false || attacking)", &["attacking"]));
+    }
+
+    #[test]
+    fn any_whitespace_separates_tokens() {
+        assert_eq!(parse("running\n\t&&\r\n  jumping").unwrap(),
+                   Rule::And(flag("running"), flag("jumping")));
+    }
+
+    #[test]
+    fn unknown_flags_are_rejected() {
+        let error = parse("running && flying").unwrap_err();
+        assert_eq!(error.rule, "running && flying");
+        assert!(error.reason.contains("unknown flag \"flying\""), "{}", error.reason);
+    }
+
+    #[test]
+    fn malformed_rules_are_rejected() {
+        for source in &["", "running &", "running && ", "(running", "running)", "running jumping",
+                        "&& running", "running + jumping", "!"] {
+            assert!(parse(source).is_err(), "\"{}\" was accepted", source);
+        }
+    }
+}
@@ -0,0 +1,254 @@
+use crate::{
+    error::{GameError, GameResult},
+    settings::{Settings, CharacterModel, AnimationSettings, Scene},
+};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssetKind {
+    Model,
+    Scene,
+    Animation,
+}
+
+impl AssetKind {
+    /// Subdirectory of the data directory that holds assets of this kind.
+    pub fn directory(self) -> &'static str {
+        match self {
+            AssetKind::Model => "models",
+            AssetKind::Scene => "scenes",
+            AssetKind::Animation => "animations",
+        }
+    }
+}
+
+/// A file to load, along with the settings key it is declared at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetFile {
+    pub key: String,
+    pub path: PathBuf,
+}
+
+/// Everything the settings declare about a character.
+#[derive(Debug)]
+pub struct CharacterAsset<'a> {
+    pub name: String,
+    /// Where `model` and `animations` are declared.
+    pub model_key: String,
+    pub animations_key: String,
+    pub model: &'a CharacterModel,
+    pub animations: &'a AnimationSettings,
+    pub model_file: AssetFile,
+    /// File of every animation state, by state name.
+    pub animation_files: HashMap<String, AssetFile>,
+}
+
+#[derive(Debug)]
+pub struct SceneAsset<'a> {
+    pub name: String,
+    pub settings: &'a Scene,
+    pub file: AssetFile,
+}
+
+/// Looks up the assets declared in settings by their name.
+pub struct AssetRegistry<'a> {
+    settings: &'a Settings,
+}
+
+impl<'a> AssetRegistry<'a> {
+    pub fn new(settings: &'a Settings) -> Self {
+        Self { settings }
+    }
+
+    pub fn file(&self, kind: AssetKind, key: String, file: &str) -> AssetFile {
+        AssetFile {
+            key,
+            path: PathBuf::from(&self.settings.data_dir).join(kind.directory()).join(file),
+        }
+    }
+
+    /// `name` is a key under `scenes`.
+    pub fn scene(&self, name: &str) -> GameResult<SceneAsset<'a>> {
+        let settings = self.settings.scenes
+            .get(name)
+            .ok_or_else(|| undefined("scenes", name))?;
+
+        Ok(SceneAsset {
+            name: name.to_owned(),
+            settings,
+            file: self.file(AssetKind::Scene, format!("scenes.{}.model", name), &settings.model),
+        })
+    }
+
+    pub fn weapon(&self) -> AssetFile {
+        self.file(AssetKind::Model, "models.weapon.model".to_owned(), &self.settings.models.weapon.model)
+    }
+
+    /// `name` is either "player" or the name of a bot archetype.
+    pub fn character(&self, name: &str) -> GameResult<CharacterAsset<'a>> {
+        let settings = self.settings;
+
+        let (model_key, model, animations_key, animations) = if name == "player" {
+            ("models.player".to_owned(), &settings.models.player,
+             "animations.player".to_owned(), &settings.animations.player)
+        } else {
+            let archetype = settings.bot.archetypes
+                .get(name)
+                .ok_or_else(|| undefined("bot.archetypes", name))?;
+            let animations = settings.animations.bots
+                .get(&archetype.animations)
+                .ok_or_else(|| undefined(&format!("bot.archetypes.{}.animations", name), &archetype.animations))?;
+
+            (format!("bot.archetypes.{}.model", name), &archetype.model,
+             format!("animations.bots.{}", archetype.animations), animations)
+        };
+
+        let animation_files = animations.states
+            .iter()
+            .map(|(state, state_settings)| {
+                let key = format!("{}.states.{}.file", animations_key, state);
+                (state.clone(), self.file(AssetKind::Animation, key, &state_settings.file))
+            })
+            .collect();
+
+        Ok(CharacterAsset {
+            name: name.to_owned(),
+            model_file: self.file(AssetKind::Model, format!("{}.model", model_key), &model.model),
+            model_key,
+            animations_key,
+            model,
+            animations,
+            animation_files,
+        })
+    }
+
+    /// Names of all bot archetypes, sorted.
+    pub fn bot_archetypes(&self) -> Vec<&'a str> {
+        let mut names = self.settings.bot.archetypes
+            .keys()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+        names
+    }
+}
+
+fn undefined(key: &str, name: &str) -> GameError {
+    GameError::Undefined {
+        key: key.to_owned(),
+        name: name.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::{AnimationStateSettings, SettingsSources};
+
+    fn animations(files: &[&str]) -> AnimationSettings {
+        AnimationSettings {
+            entry: "idle".to_owned(),
+            states: files.iter()
+                .map(|file| {
+                    let state = AnimationStateSettings { file: file.to_string(), looped: true, rewind: None };
+                    (file.trim_end_matches(".fbx").to_owned(), state)
+                })
+                .collect(),
+            transitions: Vec::new(),
+        }
+    }
+
+    /// The built-in settings, with everything the registry looks up replaced by known values.
+    fn settings() -> Settings {
+        let sources = SettingsSources {
+            directory: PathBuf::from("no such directory"),
+            ..SettingsSources::repository()
+        };
+        let mut settings = Settings::load(&sources).unwrap();
+
+        settings.data_dir = "fixture".to_owned();
+        settings.models.player.model = "player.fbx".to_owned();
+        settings.animations.player = animations(&["idle.fbx", "run.fbx"]);
+        settings.animations.bots = vec![("melee".to_owned(), animations(&["idle.fbx", "swing.fbx", "walk.fbx"]))]
+            .into_iter()
+            .collect();
+
+        let mut grunt = settings.bot.archetypes["cyclops"].clone();
+        grunt.model.model = "grunt.fbx".to_owned();
+        grunt.animations = "melee".to_owned();
+        settings.bot.archetypes = vec![("grunt".to_owned(), grunt)].into_iter().collect();
+
+        let mut arena = settings.scenes.remove("main").unwrap();
+        arena.model = "arena.rgs".to_owned();
+        settings.scenes.insert("arena".to_owned(), arena);
+        settings.level = "arena".to_owned();
+
+        settings
+    }
+
+    #[test]
+    fn player_is_looked_up_by_name() {
+        let settings = settings();
+        let player = AssetRegistry::new(&settings).character("player").unwrap();
+
+        assert_eq!(player.model_file.key, "models.player.model");
+        assert_eq!(player.model_file.path, PathBuf::from("fixture/models/player.fbx"));
+        assert_eq!(player.animations_key, "animations.player");
+        assert_eq!(player.animation_files["run"].path, PathBuf::from("fixture/animations/run.fbx"));
+        assert_eq!(player.animation_files.len(), 2);
+    }
+
+    #[test]
+    fn archetypes_resolve_their_animation_set() {
+        let settings = settings();
+        let assets = AssetRegistry::new(&settings);
+        assert_eq!(assets.bot_archetypes(), vec!["grunt"]);
+
+        let grunt = assets.character("grunt").unwrap();
+        assert_eq!(grunt.model_key, "bot.archetypes.grunt.model");
+        assert_eq!(grunt.model_file.path, PathBuf::from("fixture/models/grunt.fbx"));
+        assert_eq!(grunt.animations_key, "animations.bots.melee");
+
+        let swing = &grunt.animation_files["swing"];
+        assert_eq!(swing.key, "animations.bots.melee.states.swing.file");
+        assert_eq!(swing.path, PathBuf::from("fixture/animations/swing.fbx"));
+        assert_eq!(grunt.animation_files.len(), 3);
+    }
+
+    #[test]
+    fn unknown_names_report_the_key() {
+        let mut settings = settings();
+        settings.bot.archetypes.get_mut("grunt").unwrap().animations = "no such set".to_owned();
+        let assets = AssetRegistry::new(&settings);
+
+        match assets.character("no such character") {
+            Err(GameError::Undefined { key, name }) => {
+                assert_eq!(key, "bot.archetypes");
+                assert_eq!(name, "no such character");
+            }
+            other => panic!("unexpected result: {:?}", other.map(|character| character.name)),
+        }
+
+        match assets.character("grunt") {
+            Err(GameError::Undefined { key, name }) => {
+                assert_eq!(key, "bot.archetypes.grunt.animations");
+                assert_eq!(name, "no such set");
+            }
+            other => panic!("unexpected result: {:?}", other.map(|character| character.name)),
+        }
+
+        assert!(matches!(assets.scene("no such scene"), Err(GameError::Undefined { .. })));
+    }
+
+    #[test]
+    fn level_scene_is_looked_up_by_name() {
+        let settings = settings();
+        let scene = AssetRegistry::new(&settings).scene(&settings.level).unwrap();
+
+        assert_eq!(scene.file.key, "scenes.arena.model");
+        assert_eq!(scene.file.path, PathBuf::from("fixture/scenes/arena.rgs"));
+    }
+}
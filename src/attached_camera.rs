@@ -1,15 +1,22 @@
-use crate::{settings::CameraSettings, rotating_camera::RotatingCamera};
+use crate::{
+    settings::CameraSettings,
+    rotating_camera::RotatingCamera,
+    keyboard_input::InputEvent,
+    mouse_look::MouseLook,
+};
 use rg3d::{
-    core::algebra::{Vector3, UnitQuaternion},
+    core::algebra::{Vector2, Vector3, UnitQuaternion},
     engine::RigidBodyHandle,
     core::pool::Handle,
     scene::{Scene, node::Node},
-    event::{DeviceEvent, Event}
 };
 
 pub struct AttachedCamera {
     pub camera: RotatingCamera,
     pub body: RigidBodyHandle,
+    mouse: MouseLook,
+    invert_y: bool,
+    pitch_limits: (f32, f32),
 }
 
 impl AttachedCamera {
@@ -19,22 +26,51 @@ impl AttachedCamera {
         Self { 
             camera,
             body,
+            mouse: MouseLook::new(settings),
+            invert_y: settings.invert_y,
+            pitch_limits: settings.pitch_limits,
         }
     }
 
-    pub fn process_input_event(&mut self, event: &Event<()>) {
-        if let Event::DeviceEvent { event, .. } = event {
-            if let DeviceEvent::MouseMotion { delta } = event {
-                self.camera.yaw -= delta.0 as f32 * 0.3;
+    pub fn apply_settings(&mut self, scene: &mut Scene, settings: &CameraSettings) {
+        self.camera.apply_settings(scene, settings);
+        self.mouse.apply_settings(settings);
+        self.invert_y = settings.invert_y;
+        self.pitch_limits = settings.pitch_limits;
+    }
 
-                self.camera.pitch += delta.1 as f32 * 0.01;
-                self.camera.pitch = self.camera.pitch
-                    .clamp(-90.0f32.to_radians(), 90.0f32.to_radians());
-            }
+    pub fn process_input_event(&mut self, event: &InputEvent) {
+        match event {
+            InputEvent::MouseMotion { delta } => self.mouse.process_motion(*delta),
+            InputEvent::ReleaseAll => self.mouse.clear(),
+            _ => (),
         }
     }
 
-    pub fn update(&mut self, scene: &mut Scene) {
+    /// Turns by `speed` degrees per second over `delta` seconds, x to the right and y up.
+    pub fn turn(&mut self, speed: Vector2<f32>, delta: f32) {
+        let down = if self.invert_y { speed.y } else { -speed.y };
+        self.rotate(Vector2::new(speed.x, down) * delta);
+    }
+
+    /// Forgets mouse motion that was not applied yet.
+    pub fn clear(&mut self) {
+        self.mouse.clear();
+    }
+
+    /// Turns by `angles` in degrees, x to the right and y down.
+    fn rotate(&mut self, angles: Vector2<f32>) {
+        self.camera.yaw -= angles.x;
+
+        let (lowest, highest) = self.pitch_limits;
+        self.camera.pitch = (self.camera.pitch + angles.y).clamp(lowest, highest);
+    }
+
+    /// Applies mouse motion received since the last tick of `delta` seconds.
+    pub fn update(&mut self, scene: &mut Scene, delta: f32) {
+        let turn = self.mouse.update(delta);
+        self.rotate(turn);
+
         let body = scene
             .physics
             .bodies
@@ -51,7 +87,7 @@ impl AttachedCamera {
             .local_transform_mut()
             .set_rotation(UnitQuaternion::from_axis_angle(
                     &Vector3::x_axis(),
-                    self.camera.pitch,
+                    self.camera.pitch.to_radians(),
                     ));
     }
 }
@@ -0,0 +1,355 @@
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Success,
+    Failure,
+    Running,
+}
+
+/// Node of a tree as written in settings. Children are referenced by name,
+/// so that every node fits on a single TOML line.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeDefinition<C, A> {
+    /// Runs children in order until one of them does not fail.
+    Selector(Vec<String>),
+    /// Runs children in order until one of them does not succeed.
+    Sequence(Vec<String>),
+    Condition(C),
+    Action(A),
+    Inverter(String),
+    /// Turns any result of the child except `Running` into success.
+    Succeeder(String),
+    /// Fails without running the child for `seconds` after it last succeeded.
+    Cooldown { child: String, seconds: f32 },
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TreeDefinition<C, A> {
+    pub root: String,
+    pub nodes: HashMap<String, NodeDefinition<C, A>>,
+}
+
+/// Evaluates the leaves of a tree against some game state.
+pub trait Leaves<C, A> {
+    fn check(&mut self, condition: &C) -> bool;
+    fn run(&mut self, action: &A) -> Status;
+}
+
+enum Node<C, A> {
+    Selector(Vec<usize>),
+    Sequence(Vec<usize>),
+    Condition(C),
+    Action(A),
+    Inverter(usize),
+    Succeeder(usize),
+    Cooldown { child: usize, seconds: f32, last_success: Option<f64> },
+}
+
+pub struct BehaviorTree<C, A> {
+    nodes: Vec<Node<C, A>>,
+    root: usize,
+}
+
+impl<C: Clone, A: Clone> BehaviorTree<C, A> {
+    pub fn new(definition: &TreeDefinition<C, A>) -> Result<Self, String> {
+        let mut builder = Builder {
+            definition,
+            nodes: Vec::new(),
+            indices: HashMap::new(),
+            path: Vec::new(),
+        };
+        let root = builder.build(&definition.root)?;
+
+        Ok(Self {
+            nodes: builder.nodes,
+            root,
+        })
+    }
+
+    /// Evaluates the whole tree from the root. `time` is used by cooldowns.
+    pub fn tick<L: Leaves<C, A>>(&mut self, time: f64, leaves: &mut L) -> Status {
+        self.tick_node(self.root, time, leaves)
+    }
+
+    fn tick_node<L: Leaves<C, A>>(&mut self, index: usize, time: f64, leaves: &mut L) -> Status {
+        match &self.nodes[index] {
+            Node::Selector(children) => {
+                for child in children.clone() {
+                    match self.tick_node(child, time, leaves) {
+                        Status::Failure => (),
+                        status => return status,
+                    }
+                }
+                Status::Failure
+            }
+            Node::Sequence(children) => {
+                for child in children.clone() {
+                    match self.tick_node(child, time, leaves) {
+                        Status::Success => (),
+                        status => return status,
+                    }
+                }
+                Status::Success
+            }
+            Node::Condition(condition) => {
+                if leaves.check(condition) {
+                    Status::Success
+                } else {
+                    Status::Failure
+                }
+            }
+            Node::Action(action) => leaves.run(action),
+            &Node::Inverter(child) => match self.tick_node(child, time, leaves) {
+                Status::Success => Status::Failure,
+                Status::Failure => Status::Success,
+                Status::Running => Status::Running,
+            },
+            &Node::Succeeder(child) => match self.tick_node(child, time, leaves) {
+                Status::Running => Status::Running,
+                _ => Status::Success,
+            },
+            &Node::Cooldown { child, seconds, last_success } => {
+                if let Some(last) = last_success {
+                    if time - last < seconds as f64 {
+                        return Status::Failure;
+                    }
+                }
+
+                let status = self.tick_node(child, time, leaves);
+                if status == Status::Success {
+                    if let Node::Cooldown { last_success, .. } = &mut self.nodes[index] {
+                        *last_success = Some(time);
+                    }
+                }
+                status
+            }
+        }
+    }
+}
+
+struct Builder<'a, C, A> {
+    definition: &'a TreeDefinition<C, A>,
+    nodes: Vec<Node<C, A>>,
+    indices: HashMap<&'a str, usize>,
+    /// Names being built, to detect cycles.
+    path: Vec<&'a str>,
+}
+
+impl<'a, C: Clone, A: Clone> Builder<'a, C, A> {
+    fn build(&mut self, name: &'a str) -> Result<usize, String> {
+        if let Some(&index) = self.indices.get(name) {
+            return Ok(index);
+        }
+        if self.path.contains(&name) {
+            return Err(format!("node \"{}\" is its own descendant", name));
+        }
+
+        let (name, definition) = self.definition.nodes
+            .get_key_value(name)
+            .ok_or_else(|| format!("node \"{}\" is not defined", name))?;
+        let name = name.as_str();
+
+        self.path.push(name);
+        let node = match definition {
+            NodeDefinition::Selector(children) => Node::Selector(self.build_all(children)?),
+            NodeDefinition::Sequence(children) => Node::Sequence(self.build_all(children)?),
+            NodeDefinition::Condition(condition) => Node::Condition(condition.clone()),
+            NodeDefinition::Action(action) => Node::Action(action.clone()),
+            NodeDefinition::Inverter(child) => Node::Inverter(self.build(child)?),
+            NodeDefinition::Succeeder(child) => Node::Succeeder(self.build(child)?),
+            NodeDefinition::Cooldown { child, seconds } => Node::Cooldown {
+                child: self.build(child)?,
+                seconds: *seconds,
+                last_success: None,
+            },
+        };
+        self.path.pop();
+
+        let index = self.nodes.len();
+        self.nodes.push(node);
+        self.indices.insert(name, index);
+
+        Ok(index)
+    }
+
+    fn build_all(&mut self, names: &'a [String]) -> Result<Vec<usize>, String> {
+        if names.is_empty() {
+            return Err(format!("node \"{}\" has no children", self.path.last().unwrap()));
+        }
+        names.iter().map(|name| self.build(name)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Conditions are true if listed in `facts`; actions return their entry in
+    /// `results`, success by default, and are logged when run.
+    #[derive(Default)]
+    struct Script {
+        facts: Vec<&'static str>,
+        results: HashMap<&'static str, Status>,
+        log: Vec<String>,
+    }
+
+    impl Leaves<String, String> for Script {
+        fn check(&mut self, condition: &String) -> bool {
+            self.facts.contains(&condition.as_str())
+        }
+
+        fn run(&mut self, action: &String) -> Status {
+            self.log.push(action.clone());
+            self.results.get(action.as_str()).copied().unwrap_or(Status::Success)
+        }
+    }
+
+    fn tree(source: &str) -> Result<BehaviorTree<String, String>, String> {
+        let definition: TreeDefinition<String, String> = toml::from_str(source).unwrap();
+        BehaviorTree::new(&definition)
+    }
+
+    const COMBAT: &str = r#"
+        root = "root"
+        [nodes]
+        root = { selector = ["attack", "chase", "idle"] }
+        attack = { sequence = ["in_range", "strike"] }
+        chase = { sequence = ["sees_target", "approach"] }
+        in_range = { condition = "in_range" }
+        sees_target = { condition = "sees_target" }
+        strike = { action = "strike" }
+        approach = { action = "approach" }
+        idle = { action = "idle" }
+    "#;
+
+    #[test]
+    fn selector_runs_the_first_child_that_does_not_fail() {
+        let mut tree = tree(COMBAT).unwrap();
+        let mut script = Script { facts: vec!["sees_target"], ..Default::default() };
+
+        assert_eq!(tree.tick(0.0, &mut script), Status::Success);
+        assert_eq!(script.log, vec!["approach"]);
+
+        script.facts.clear();
+        script.results.insert("idle", Status::Failure);
+        assert_eq!(tree.tick(0.1, &mut script), Status::Failure);
+        assert_eq!(script.log, vec!["approach", "idle"]);
+    }
+
+    #[test]
+    fn sequence_stops_at_the_first_child_that_does_not_succeed() {
+        let mut tree = tree(COMBAT).unwrap();
+        let mut script = Script { facts: vec!["in_range"], ..Default::default() };
+        script.results.insert("strike", Status::Failure);
+
+        // The attack sequence fails at the strike, so the selector falls through to idle.
+        assert_eq!(tree.tick(0.0, &mut script), Status::Success);
+        assert_eq!(script.log, vec!["strike", "idle"]);
+    }
+
+    #[test]
+    fn running_actions_are_resumed_on_the_next_tick() {
+        let mut tree = tree(COMBAT).unwrap();
+        let mut script = Script { facts: vec!["sees_target"], ..Default::default() };
+        script.results.insert("approach", Status::Running);
+
+        assert_eq!(tree.tick(0.0, &mut script), Status::Running);
+        assert_eq!(tree.tick(0.1, &mut script), Status::Running);
+        assert_eq!(script.log, vec!["approach", "approach"]);
+
+        // Conditions are checked again on every tick, so a higher priority branch takes over.
+        script.facts.push("in_range");
+        assert_eq!(tree.tick(0.2, &mut script), Status::Success);
+        assert_eq!(script.log, vec!["approach", "approach", "strike"]);
+    }
+
+    #[test]
+    fn inverter_and_succeeder_change_results() {
+        let mut tree = tree(r#"
+            root = "root"
+            [nodes]
+            root = { sequence = ["not_hurt", "always"] }
+            not_hurt = { inverter = "hurt" }
+            hurt = { condition = "hurt" }
+            always = { succeeder = "heal" }
+            heal = { action = "heal" }
+        "#).unwrap();
+        let mut script = Script::default();
+        script.results.insert("heal", Status::Failure);
+
+        assert_eq!(tree.tick(0.0, &mut script), Status::Success);
+
+        script.results.insert("heal", Status::Running);
+        assert_eq!(tree.tick(0.1, &mut script), Status::Running);
+
+        script.facts.push("hurt");
+        assert_eq!(tree.tick(0.2, &mut script), Status::Failure);
+        assert_eq!(script.log.len(), 2);
+    }
+
+    #[test]
+    fn cooldown_fails_until_it_has_passed_since_the_last_success() {
+        let mut tree = tree(r#"
+            root = "limited"
+            [nodes]
+            limited = { cooldown = { child = "shout", seconds = 1.0 } }
+            shout = { action = "shout" }
+        "#).unwrap();
+        let mut script = Script::default();
+        script.results.insert("shout", Status::Running);
+
+        // Only a success starts the cooldown.
+        assert_eq!(tree.tick(0.0, &mut script), Status::Running);
+        script.results.remove("shout");
+        assert_eq!(tree.tick(0.1, &mut script), Status::Success);
+        assert_eq!(tree.tick(0.5, &mut script), Status::Failure);
+        assert_eq!(tree.tick(1.1, &mut script), Status::Success);
+        assert_eq!(script.log.len(), 3);
+    }
+
+    #[test]
+    fn invalid_trees_are_rejected() {
+        let error = |source: &str| tree(source).err().unwrap_or_else(|| panic!("accepted: {}", source));
+
+        assert_eq!(error(r#"
+            root = "a"
+            [nodes]
+            a = { sequence = ["b"] }
+            b = { inverter = "a" }
+        "#), "node \"a\" is its own descendant");
+
+        assert_eq!(error(r#"
+            root = "a"
+            [nodes]
+            a = { selector = ["b", "missing"] }
+            b = { action = "b" }
+        "#), "node \"missing\" is not defined");
+
+        assert_eq!(error(r#"
+            root = "missing"
+            [nodes]
+        "#), "node \"missing\" is not defined");
+
+        assert_eq!(error(r#"
+            root = "a"
+            [nodes]
+            a = { selector = [] }
+        "#), "node \"a\" has no children");
+    }
+
+    #[test]
+    fn shared_nodes_are_built_once() {
+        let tree = tree(r#"
+            root = "a"
+            [nodes]
+            a = { selector = ["b", "c"] }
+            b = { inverter = "leaf" }
+            c = { succeeder = "leaf" }
+            leaf = { action = "leaf" }
+        "#).unwrap();
+
+        assert_eq!(tree.nodes.len(), 4);
+    }
+}
@@ -1,17 +1,22 @@
 use crate::{
-    SETTINGS,
     GameTime,
     character::Character, 
-    character_animation::{CharacterAnimations, CharacterAnimationController},
+    character_animation::CharacterAnimationController,
     character_body::CharacterBody,
-    request_model, character_body, character_animations,
-    message::Message,
+    resource_helper::CharacterResources,
+    message::{Message, MessageHandler},
     bot_ai::BotAi,
+    navmesh::NavMesh,
+    perception::Target,
+    steering::Neighbour,
+    waves::Difficulty,
+    settings::{Settings, BotArchetype, BotAiSettings, CharacterSpeedSettings},
+    error::{GameError, GameResult},
 };
 use rg3d::{
-    engine::resource_manager::ResourceManager,
     scene::Scene,
     core::algebra::Vector3,
+    engine::RigidBodyHandle,
 };
 use std::{
     ops::{Deref, DerefMut},
@@ -21,6 +26,11 @@ use std::{
 pub struct Bot {
     pub character: Character,
     pub ai: BotAi,
+    active: bool,
+    archetype: String,
+    difficulty: Difficulty,
+    /// Body of the player; noises of anything else, other bots included, are ignored.
+    target: RigidBodyHandle,
 }
 
 impl Deref for Bot {
@@ -38,32 +48,152 @@ impl DerefMut for Bot {
 }
 
 impl Bot {
-    pub async fn new(
+    /// `resources` are those of the archetype the bot is spawned from.
+    pub fn new(
         scene: &mut Scene,
-        resource_manager: &ResourceManager,
+        settings: &Settings,
+        resources: &CharacterResources,
         sender: Sender<Message>,
-        position: Vector3<f32>
-    ) -> Self {
-        let body = character_body!(resource_manager, scene, bot, position);
+        position: Vector3<f32>,
+        difficulty: Difficulty,
+        target: RigidBodyHandle,
+    ) -> GameResult<Self> {
+        let name = &resources.name;
+        let archetype = settings.bot.archetypes
+            .get(name)
+            .ok_or_else(|| GameError::Undefined { key: "bot.archetypes".to_owned(), name: name.clone() })?;
+        let behavior = settings.bot.behaviors
+            .get(&archetype.behavior)
+            .ok_or_else(|| GameError::Undefined {
+                key: format!("bot.archetypes.{}.behavior", name),
+                name: archetype.behavior.clone(),
+            })?;
 
-        let settings = &SETTINGS.read().unwrap();
+        let body = CharacterBody::from_resources(scene, resources, position)?;
 
-        let animations = character_animations!(scene, resource_manager, &body, bot, settings);
-        let animation_controller = CharacterAnimationController::new(animations);
+        let animation_controller = CharacterAnimationController::from_resources(scene, &body, resources)?;
 
-        let character = Character::new(scene, body, animation_controller);
+        let mut health = archetype.health.clone();
+        health.max *= difficulty.health;
+        let character = Character::new(scene, body, animation_controller, &health, sender);
 
-        let ai = BotAi::new(settings.bot.speed.clone());
+        let (speed, ai_settings) = Self::scaled_settings(archetype, difficulty);
 
-        Self {
+        let ai = BotAi::new(speed,
+                            ai_settings,
+                            archetype.perception.clone(),
+                            settings.bot.steering.clone(),
+                            behavior,
+                            position)
+            .map_err(|reason| GameError::Behavior {
+                key: format!("bot.behaviors.{}", archetype.behavior),
+                reason,
+            })?;
+
+        Ok(Self {
             character,
             ai,
+            active: true,
+            archetype: name.clone(),
+            difficulty,
+            target,
+        })
+    }
+
+    fn scaled_settings(archetype: &BotArchetype,
+                       difficulty: Difficulty) -> (CharacterSpeedSettings, BotAiSettings) {
+        let mut speed = archetype.speed.clone();
+        speed.run *= difficulty.speed;
+        let mut ai_settings = archetype.ai.clone();
+        ai_settings.damage *= difficulty.damage;
+
+        (speed, ai_settings)
+    }
+
+    /// Adopts the values of `settings` that can change while the game runs.
+    pub fn apply_settings(&mut self, settings: &Settings) {
+        // An archetype removed from the settings keeps its old values until the bot dies.
+        if let Some(archetype) = settings.bot.archetypes.get(&self.archetype) {
+            let (speed, ai_settings) = Self::scaled_settings(archetype, self.difficulty);
+            self.ai.apply_settings(speed,
+                                   ai_settings,
+                                   archetype.perception.clone(),
+                                   settings.bot.steering.clone());
+        }
+    }
+
+    /// Returns the damage dealt to `target` during this tick, if any.
+    pub fn update(&mut self,
+                  scene: &mut Scene,
+                  navmesh: &NavMesh,
+                  time: GameTime,
+                  target: Option<Target>,
+                  neighbours: &[Neighbour]) -> Option<f32> {
+        if self.is_dead() || !self.active {
+            return None;
+        }
+
+        let health = (self.character.health, self.character.max_health);
+        let output = self.ai.update(scene, navmesh, &mut self.character.body,
+                                    time, target, neighbours, health);
+
+        if output.attack_started {
+            let position = scene.graph[self.character.body.pivot].global_position();
+            self.character.send(Message::AttackStarted {
+                attacker: self.character.body.body,
+                position,
+            });
+        }
+
+        self.character.update(scene, time, output.animation);
+
+        if output.attack_landed {
+            Some(self.ai.damage())
+        } else {
+            None
         }
     }
 
-    pub fn update(&mut self, scene: &mut Scene, time: GameTime) {
-        let animation_input = self.ai.update(scene, &mut self.character.body);
-        self.character.update(scene, time, animation_input);
+}
+
+impl MessageHandler for Bot {
+    fn handle_message(&mut self, message: &Message) {
+        match *message {
+            Message::LevelFinished => self.active = false,
+            _ => {
+                if let Some(position) = noise_of(message, self.target) {
+                    self.ai.perception.hear(position);
+                }
+            }
+        }
     }
+}
 
+/// Where the noise `message` was made, if it was made by `source`.
+fn noise_of(message: &Message, source: RigidBodyHandle) -> Option<Vector3<f32>> {
+    match *message {
+        Message::AttackStarted { attacker, position } if attacker == source => Some(position),
+        Message::Footstep { source: maker, position } if maker == source => Some(position),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_noises_of_the_target_are_heard() {
+        let (player, other_bot) = (RigidBodyHandle::from_raw_parts(0, 0), RigidBodyHandle::from_raw_parts(1, 0));
+        let position = Vector3::new(1.0, 0.0, 2.0);
+
+        let footstep = |source| Message::Footstep { source, position };
+        let attack = |attacker| Message::AttackStarted { attacker, position };
+
+        assert_eq!(noise_of(&footstep(player), player), Some(position));
+        assert_eq!(noise_of(&attack(player), player), Some(position));
+        assert_eq!(noise_of(&footstep(other_bot), player), None);
+        assert_eq!(noise_of(&attack(other_bot), player), None);
+        assert_eq!(noise_of(&Message::BotSpawned { body: player }, player), None);
+    }
 }
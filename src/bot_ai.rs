@@ -1,66 +1,521 @@
 use crate::{
-    SETTINGS,
-    settings::CharacterSpeedSettings,
-    keyboard_input::{KeyMap, Action},
+    GameTime,
+    settings::{CharacterSpeedSettings, BotAiSettings, PerceptionSettings, SteeringSettings},
     character_body::CharacterBody,
     character_animation::CharacterAnimationInput,
+    behavior_tree::{BehaviorTree, Leaves, Status, TreeDefinition},
+    navmesh::NavMesh,
+    perception::{Perception, Target},
+    steering::{self, Neighbour},
 };
 use rg3d::{
-    scene::{Scene, node::Node},
-    core::{algebra::{Vector3, UnitQuaternion}, pool::Handle},
-    event::{Event, WindowEvent, ElementState},
+    engine::ColliderHandle,
+    scene::Scene,
+    core::algebra::{Vector3, UnitQuaternion},
 };
-use std::collections::HashMap;
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum BotCondition {
+    /// The target is seen during this tick.
+    HasTarget,
+    /// Horizontal distance to the target is at most this value.
+    TargetWithin(f32),
+    TargetBeyond(f32),
+    /// Health is below this fraction of the maximum.
+    HealthBelow(f32),
+    HasLastSeen,
+    AtHome,
+    /// The bot chased or attacked on the previous tick.
+    Engaged,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum BotAction {
+    Idle,
+    Chase,
+    Attack,
+    ReturnHome,
+    MoveToLastSeen,
+    /// Drops where the target was last seen or heard, so that it isn't searched for.
+    ForgetTarget,
+}
+
+pub type BotBehavior = TreeDefinition<BotCondition, BotAction>;
+
+/// What the bot knows about the world, refreshed every tick.
+#[derive(Debug, Default, Clone)]
+pub struct Blackboard {
+    pub position: Vector3<f32>,
+    pub home: Vector3<f32>,
+    /// Position of the target, while it is seen.
+    pub target: Option<Vector3<f32>>,
+    /// Where the target was last seen or heard.
+    pub last_seen: Option<Vector3<f32>>,
+    pub health: f32,
+    pub max_health: f32,
+    pub engaged: bool,
+    /// Where to stand around the target when chasing it in a group.
+    pub slot: Option<Vector3<f32>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct AttackProgress {
+    start_time: f64,
+    landed: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct BotAiOutput {
+    pub animation: CharacterAnimationInput,
+    pub attack_started: bool,
+    /// Set on the tick the attack connects with a target within range.
+    pub attack_landed: bool,
+}
 
 pub struct BotAi {
     speed: CharacterSpeedSettings,
+    settings: BotAiSettings,
+    steering: SteeringSettings,
+    tree: BehaviorTree<BotCondition, BotAction>,
+    pub perception: Perception,
+    pub blackboard: Blackboard,
+    attack: Option<AttackProgress>,
+    last_attack_time: Option<f64>,
+    path: Path,
+}
+
+/// Waypoints towards `goal`, the next one last.
+#[derive(Debug, Default)]
+struct Path {
+    goal: Option<Vector3<f32>>,
+    waypoints: Vec<Vector3<f32>>,
 }
 
 impl BotAi {
-    pub fn new(speed: CharacterSpeedSettings) -> Self {
-        Self { 
+    pub fn new(speed: CharacterSpeedSettings,
+               settings: BotAiSettings,
+               perception: PerceptionSettings,
+               steering: SteeringSettings,
+               behavior: &BotBehavior,
+               home: Vector3<f32>) -> Result<Self, String> {
+        Ok(Self { 
             speed,
-        }
+            settings,
+            steering,
+            tree: BehaviorTree::new(behavior)?,
+            perception: Perception::new(perception),
+            blackboard: Blackboard {
+                home,
+                ..Default::default()
+            },
+            attack: None,
+            last_attack_time: None,
+            path: Default::default(),
+        })
+    }
+
+    pub fn apply_settings(&mut self,
+                          speed: CharacterSpeedSettings,
+                          settings: BotAiSettings,
+                          perception: PerceptionSettings,
+                          steering: SteeringSettings) {
+        self.speed = speed;
+        self.settings = settings;
+        self.perception.apply_settings(perception);
+        self.steering = steering;
+    }
+
+    pub fn damage(&self) -> f32 {
+        self.settings.damage
     }
 
     pub fn update(&mut self,
-                             scene: &mut Scene, 
-                             body: &mut CharacterBody) -> CharacterAnimationInput {
+                  scene: &mut Scene,
+                  navmesh: &NavMesh,
+                  body: &mut CharacterBody,
+                  time: GameTime,
+                  target: Option<Target>,
+                  neighbours: &[Neighbour],
+                  health: (f32, f32)) -> BotAiOutput {
+        let seen = self.perception.update(scene, body, time.elapsed, target);
+
+        let blackboard = &mut self.blackboard;
+        blackboard.position = scene.graph[body.pivot].global_position();
+        blackboard.target = seen;
+        blackboard.last_seen = self.perception.last_known_position();
+        blackboard.health = health.0;
+        blackboard.max_health = health.1;
+
+        let mut context = BotContext {
+            scene,
+            navmesh,
+            body,
+            time,
+            speed: &self.speed,
+            settings: &self.settings,
+            steering: &self.steering,
+            neighbours,
+            target_collider: target.map(|target| target.collider),
+            blackboard: &mut self.blackboard,
+            perception: &mut self.perception,
+            attack: &mut self.attack,
+            last_attack_time: &mut self.last_attack_time,
+            path: &mut self.path,
+            output: Default::default(),
+            engaged: false,
+        };
+
+        // An attack that has started always plays out, whatever the tree decides.
+        if context.attack.is_some() {
+            context.run(&BotAction::Attack);
+        } else {
+            self.tree.tick(time.elapsed, &mut context);
+        }
+
+        context.blackboard.engaged = context.engaged;
+        context.output
+    }
+}
+
+struct BotContext<'a> {
+    scene: &'a mut Scene,
+    navmesh: &'a NavMesh,
+    body: &'a CharacterBody,
+    time: GameTime,
+    speed: &'a CharacterSpeedSettings,
+    settings: &'a BotAiSettings,
+    steering: &'a SteeringSettings,
+    neighbours: &'a [Neighbour],
+    target_collider: Option<ColliderHandle>,
+    blackboard: &'a mut Blackboard,
+    perception: &'a mut Perception,
+    attack: &'a mut Option<AttackProgress>,
+    last_attack_time: &'a mut Option<f64>,
+    path: &'a mut Path,
+    output: BotAiOutput,
+    engaged: bool,
+}
+
+impl<'a> Leaves<BotCondition, BotAction> for BotContext<'a> {
+    fn check(&mut self, condition: &BotCondition) -> bool {
+        check(self.blackboard, self.settings, condition)
+    }
+
+    fn run(&mut self, action: &BotAction) -> Status {
+        match action {
+            BotAction::Idle => {
+                self.stop();
+                Status::Success
+            }
+            BotAction::Chase => {
+                let target = match self.blackboard.target {
+                    Some(target) => target,
+                    None => return Status::Failure,
+                };
+                self.engaged = true;
+
+                let position = self.blackboard.position;
+                let destination = self.blackboard.slot.unwrap_or(target);
+                if horizontal_distance(target, position) > self.settings.attack_range
+                    || horizontal_distance(destination, position) > self.settings.arrive_distance {
+                    self.output.animation.running = self.navigate(destination);
+                    Status::Running
+                } else {
+                    self.face(target);
+                    self.stop();
+                    Status::Success
+                }
+            }
+            BotAction::Attack => self.attack(),
+            BotAction::ReturnHome => {
+                let home = self.blackboard.home;
+                self.move_to(home)
+            }
+            BotAction::MoveToLastSeen => {
+                let last_seen = match self.blackboard.last_seen {
+                    Some(last_seen) => last_seen,
+                    None => return Status::Failure,
+                };
+                let status = self.move_to(last_seen);
+                if status == Status::Success {
+                    self.forget_target();
+                }
+                status
+            }
+            BotAction::ForgetTarget => {
+                self.forget_target();
+                Status::Success
+            }
+        }
+    }
+}
+
+fn check(blackboard: &Blackboard, settings: &BotAiSettings, condition: &BotCondition) -> bool {
+    let target_distance = blackboard.target
+        .map(|target| horizontal_distance(target, blackboard.position));
 
-        let mut animation_input = CharacterAnimationInput::default();
+    match *condition {
+        BotCondition::HasTarget => blackboard.target.is_some(),
+        BotCondition::TargetWithin(distance) => target_distance.map_or(false, |d| d <= distance),
+        BotCondition::TargetBeyond(distance) => target_distance.map_or(false, |d| d > distance),
+        BotCondition::HealthBelow(fraction) => {
+            blackboard.health < blackboard.max_health * fraction
+        }
+        BotCondition::HasLastSeen => blackboard.last_seen.is_some(),
+        BotCondition::AtHome => {
+            horizontal_distance(blackboard.home, blackboard.position) <= settings.arrive_distance
+        }
+        BotCondition::Engaged => blackboard.engaged,
+    }
+}
 
-        let pivot = &scene.graph[body.pivot];
-        let self_position = pivot.global_position();
+impl<'a> BotContext<'a> {
+    fn attack(&mut self) -> Status {
+        let time = self.time.elapsed;
+        let settings = self.settings;
 
-        let target = Vector3::new(1.0,self_position .y, 1.0);
-        let direction = target - self_position;
-        let distance = direction.norm();
+        let mut attack = match *self.attack {
+            Some(attack) => attack,
+            None => {
+                let ready = self.last_attack_time
+                    .map_or(true, |last| time - last >= settings.attack_cooldown as f64);
+                if !ready || self.blackboard.target.is_none() {
+                    return Status::Failure;
+                }
 
-        let rigid_body = scene.physics.bodies.get_mut(&body.body).unwrap();
+                self.output.attack_started = true;
+                *self.last_attack_time = Some(time);
+                AttackProgress { start_time: time, landed: false }
+            }
+        };
 
-        // Make sure bot is facing towards the target.
-        let mut position = *rigid_body.position();
-        position.rotation = UnitQuaternion::face_towards(
-            &Vector3::new(direction.x, 0.0, direction.z),
-            &Vector3::y_axis(),
-            );
-        rigid_body.set_position(position, true);
+        self.engaged = true;
+        self.stop();
+        if let Some(target) = self.blackboard.target {
+            self.face(target);
+        }
 
-        // Move only if we're far enough from the target.
-        if distance > 0.1 {
-            // Normalize direction vector and scale it by movement speed.
-            let xz_velocity = direction.scale(1.0 / distance).scale(self.speed.run);
+        if !attack.landed && time >= attack.start_time + settings.attack_hit_time as f64 {
+            attack.landed = true;
+            self.output.attack_landed = self.blackboard.target
+                .map_or(false, |target| {
+                    horizontal_distance(target, self.blackboard.position) <= settings.attack_range
+                });
+        }
 
-            let new_velocity =
-                Vector3::new(xz_velocity.x, rigid_body.linvel().y, xz_velocity.z);
+        if time - attack.start_time < settings.attack_duration as f64 {
+            self.output.animation.attacking = true;
+            *self.attack = Some(attack);
+            Status::Running
+        } else {
+            *self.attack = None;
+            Status::Success
+        }
+    }
 
-            rigid_body.set_linvel(new_velocity, true);
+    fn forget_target(&mut self) {
+        self.blackboard.last_seen = None;
+        self.perception.forget();
+    }
 
-            animation_input.running = true;
+    fn move_to(&mut self, destination: Vector3<f32>) -> Status {
+        if horizontal_distance(destination, self.blackboard.position) <= self.settings.arrive_distance {
+            self.stop();
+            Status::Success
         } else {
-            animation_input.running = false;
+            self.output.animation.running = self.navigate(destination);
+            Status::Running
         }
+    }
+
+    /// Follows the navmesh towards `destination`. Returns true if the bot is actually moving.
+    fn navigate(&mut self, destination: Vector3<f32>) -> bool {
+        let position = self.blackboard.position;
+
+        let repath = self.path.goal
+            .map_or(true, |goal| horizontal_distance(goal, destination) > self.settings.repath_distance);
+        if repath {
+            let mut waypoints = self.navmesh.find_path(position, destination).unwrap_or_default();
+            waypoints.reverse();
+            *self.path = Path { goal: Some(destination), waypoints };
+        }
+
+        let waypoints = &mut self.path.waypoints;
+        while waypoints.len() > 1
+            && horizontal_distance(*waypoints.last().unwrap(), position) <= self.settings.arrive_distance {
+            waypoints.pop();
+        }
+
+        let last = waypoints.len() == 1;
+        match waypoints.last().copied() {
+            Some(waypoint) => self.move_towards(waypoint, last),
+            // The destination is unreachable, don't walk into a wall or off a ledge.
+            // The empty path is kept, so that it isn't searched for again every tick.
+            None => {
+                self.halt();
+                false
+            }
+        }
+    }
+
+    fn face(&mut self, target: Vector3<f32>) {
+        let direction = target - self.blackboard.position;
+
+        if let Some(direction) = Vector3::new(direction.x, 0.0, direction.z).try_normalize(f32::EPSILON) {
+            let rigid_body = self.scene.physics.bodies.get_mut(&self.body.body).unwrap();
+            let mut position = *rigid_body.position();
+            position.rotation = UnitQuaternion::face_towards(&direction, &Vector3::y_axis());
+            rigid_body.set_position(position, true);
+        }
+    }
+
+    /// Steers towards `target`, slowing down on arrival if it is the final one.
+    /// Returns true if the bot is actually moving.
+    fn move_towards(&mut self, target: Vector3<f32>, arrive: bool) -> bool {
+        let position = self.blackboard.position;
+        let steering = self.steering;
+        let speed = self.speed.run;
+
+        let slowing_radius = if arrive { steering.slowing_radius } else { 0.0 };
+        let mut velocity = steering::arrive(position, target, speed, slowing_radius);
+
+        let mut ignored = vec![self.body.collider];
+        ignored.extend(self.target_collider);
+        velocity += steering::avoid_obstacles(self.scene, position, velocity, &ignored, steering.avoidance_distance)
+            .scale(steering.avoidance_weight * speed);
+
+        velocity += self.crowd_force(true);
+
+        let velocity = steering::truncate(velocity, speed);
+        let moving = velocity.norm() > 0.1;
+
+        self.face(if moving { position + velocity } else { target });
+        self.set_horizontal_velocity(velocity);
+
+        moving
+    }
+
+    /// Separation from the nearby bots, plus cohesion and alignment with them when `flocking`.
+    fn crowd_force(&self, flocking: bool) -> Vector3<f32> {
+        let steering = self.steering;
+        let position = self.blackboard.position;
+        let own_body = self.body.body;
+        let nearby = || self.neighbours.iter().filter(move |neighbour| {
+            neighbour.body != own_body
+                && horizontal_distance(neighbour.position, position) <= steering.neighbour_radius
+        });
+
+        let mut force = steering::separation(position, nearby(), steering.separation_radius)
+            .scale(steering.separation_weight);
+
+        if flocking {
+            force += steering::cohesion(position, nearby()).scale(steering.cohesion_weight);
+            force += steering::alignment(nearby()).scale(steering.alignment_weight);
+        }
+
+        force.scale(self.speed.run)
+    }
+
+    fn set_horizontal_velocity(&mut self, velocity: Vector3<f32>) {
+        let rigid_body = self.scene.physics.bodies.get_mut(&self.body.body).unwrap();
+        let velocity = Vector3::new(velocity.x, rigid_body.linvel().y, velocity.z);
+        rigid_body.set_linvel(velocity, true);
+    }
+
+    fn stop(&mut self) {
+        *self.path = Default::default();
+        self.halt();
+    }
+
+    /// Stands still, apart from making room for the bots that are too close.
+    fn halt(&mut self) {
+        let velocity = steering::truncate(self.crowd_force(false), self.speed.run);
+        self.set_horizontal_velocity(velocity);
+    }
+}
+
+fn horizontal_distance(a: Vector3<f32>, b: Vector3<f32>) -> f32 {
+    Vector3::new(a.x - b.x, 0.0, a.z - b.z).norm()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::{Settings, SettingsSources};
+
+    /// A bot at its home at the origin, running a tree of the repository
+    /// settings against a target that is only seen within the view distance.
+    struct Scripted {
+        blackboard: Blackboard,
+        settings: BotAiSettings,
+        view_distance: f32,
+        memory: Option<Vector3<f32>>,
+        actions: Vec<String>,
+    }
+
+    impl Leaves<BotCondition, BotAction> for Scripted {
+        fn check(&mut self, condition: &BotCondition) -> bool {
+            check(&self.blackboard, &self.settings, condition)
+        }
+
+        fn run(&mut self, action: &BotAction) -> Status {
+            self.actions.push(format!("{:?}", action));
+            match action {
+                BotAction::ForgetTarget => {
+                    self.memory = None;
+                    self.blackboard.last_seen = None;
+                    Status::Success
+                }
+                BotAction::Idle => Status::Success,
+                _ => Status::Running,
+            }
+        }
+    }
+
+    impl Scripted {
+        /// Ticks `tree` with the target `distance` away along X and returns the action run last.
+        fn tick(&mut self, tree: &mut BehaviorTree<BotCondition, BotAction>, distance: f32) -> String {
+            let target = Vector3::new(distance, 0.0, 0.0);
+            let seen = Some(target).filter(|_| distance <= self.view_distance);
+            if seen.is_some() {
+                self.memory = seen;
+            }
+            self.blackboard.target = seen;
+            self.blackboard.last_seen = self.memory;
+
+            self.actions.clear();
+            tree.tick(0.0, self);
+            self.blackboard.engaged = self.actions.iter().any(|action| action == "Chase");
+            self.actions.last().cloned().unwrap_or_default()
+        }
+    }
+
+    #[test]
+    fn leashed_bots_go_home_once_the_target_is_out_of_sight() {
+        let settings = Settings::load(&SettingsSources::repository()).unwrap();
+        let archetype = &settings.bot.archetypes["cyclops"];
+        let mut tree = BehaviorTree::new(&settings.bot.behaviors[&archetype.behavior]).unwrap();
+        // The bot stays away from home, as it would while walking back.
+        let mut bot = Scripted {
+            blackboard: Blackboard {
+                home: Vector3::new(-5.0, 0.0, 0.0),
+                ..Default::default()
+            },
+            settings: archetype.ai.clone(),
+            view_distance: archetype.perception.view_distance,
+            memory: None,
+            actions: Vec::new(),
+        };
 
-        animation_input
+        assert_eq!(bot.tick(&mut tree, 5.0), "Chase");
+        assert_eq!(bot.tick(&mut tree, 11.0), "Chase");
+        // Past the leash, but still in view.
+        assert_eq!(bot.tick(&mut tree, 13.0), "ReturnHome");
+        // Out of view as well; nothing is left to search.
+        assert_eq!(bot.tick(&mut tree, 16.0), "ReturnHome");
+        assert_eq!(bot.blackboard.last_seen, None);
+        assert!(!bot.actions.iter().any(|action| action == "MoveToLastSeen"));
     }
 }
@@ -1,6 +1,7 @@
 use crate::{
     GameTime,
     message::Message,
+    settings::HealthSettings,
     character_body::CharacterBody,
     character_animation::{
         CharacterAnimationInput, CharacterAnimationController
@@ -17,6 +18,13 @@ pub struct Character {
     pub animation: CharacterAnimationController,
 
     pub health: f32,
+    pub max_health: f32,
+    /// Seconds after taking damage during which further damage is ignored.
+    pub invulnerability: f32,
+    /// Health regained per second, once `regeneration_delay` seconds passed since the last damage.
+    pub regeneration: f32,
+    pub regeneration_delay: f32,
+    last_damage_time: Option<f64>,
 
     pub sender: Option<Sender<Message>>,
 }
@@ -27,6 +35,11 @@ impl Default for Character {
             body: Default::default(),
             animation: Default::default(),
             health: 100.0,
+            max_health: 100.0,
+            invulnerability: 0.0,
+            regeneration: 0.0,
+            regeneration_delay: 0.0,
+            last_damage_time: None,
             sender: None,
         }
     }
@@ -35,18 +48,94 @@ impl Default for Character {
 impl Character {
     pub fn new(scene: &mut Scene,
                body: CharacterBody,
-               animation: CharacterAnimationController) -> Self {
+               animation: CharacterAnimationController,
+               health: &HealthSettings,
+               sender: Sender<Message>) -> Self {
         Self {
             body,
             animation,
+            health: health.max,
+            max_health: health.max,
+            invulnerability: health.invulnerability,
+            regeneration: health.regeneration,
+            regeneration_delay: health.regeneration_delay,
+            sender: Some(sender),
             ..Default::default()
         }
     }
 
     pub fn update(&mut self, scene: &mut Scene, time: GameTime, animation_input: CharacterAnimationInput) {
+        self.regenerate(time);
         self.animation.apply(scene, time, animation_input);
     }
 
+    pub fn is_dead(&self) -> bool {
+        self.health <= 0.0
+    }
+
+    pub fn is_invulnerable(&self, time: GameTime) -> bool {
+        match self.last_damage_time {
+            Some(last) => time.elapsed - last < self.invulnerability as f64,
+            None => false,
+        }
+    }
+
+    /// Returns true if the damage was applied, i.e. the character was alive
+    /// and not invulnerable.
+    pub fn apply_damage(&mut self, amount: f32, time: GameTime) -> bool {
+        if self.is_dead() || self.is_invulnerable(time) {
+            return false;
+        }
+
+        self.health = (self.health - amount).max(0.0);
+        self.last_damage_time = Some(time.elapsed);
+
+        self.send(Message::DamageDealt {
+            target: self.body.body,
+            amount,
+            health: self.health,
+        });
+
+        if self.is_dead() {
+            self.send(Message::CharacterDied { body: self.body.body });
+        }
+
+        true
+    }
+
+    pub fn heal(&mut self, amount: f32) {
+        if self.is_dead() {
+            return;
+        }
+
+        self.health = (self.health + amount).min(self.max_health);
+
+        self.send(Message::Healed {
+            target: self.body.body,
+            amount,
+            health: self.health,
+        });
+    }
+
+    /// Heals by the regeneration of one tick, unless damaged too recently.
+    pub fn regenerate(&mut self, time: GameTime) {
+        if self.regeneration <= 0.0 || self.health >= self.max_health {
+            return;
+        }
+
+        let recently_damaged = self.last_damage_time
+            .map_or(false, |last| time.elapsed - last < self.regeneration_delay as f64);
+        if !recently_damaged {
+            self.heal(self.regeneration * time.delta);
+        }
+    }
+
+    pub fn clean_up(&mut self, scene: &mut Scene) {
+        self.animation.clean_up(scene);
+        scene.remove_node(self.body.pivot);
+        scene.physics.remove_body(&self.body.body);
+    }
+
     pub fn set_position(&mut self, physics: &mut Physics, position: Vector3<f32>) {
         let body = physics.bodies.get_mut(&self.body.body).unwrap();
         let mut body_position = *body.position();
@@ -63,5 +152,58 @@ impl Character {
             .translation
             .vector
     }
+
+    pub fn send(&self, message: Message) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(message);
+        }
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    fn at(elapsed: f64) -> GameTime {
+        GameTime { elapsed, delta: 0.5 }
+    }
+
+    #[test]
+    fn health_regenerates_after_the_delay() {
+        let (sender, receiver) = mpsc::channel();
+        let mut character = Character {
+            regeneration: 10.0,
+            regeneration_delay: 2.0,
+            sender: Some(sender),
+            ..Default::default()
+        };
+
+        assert!(character.apply_damage(50.0, at(1.0)));
+        character.regenerate(at(2.5));
+        assert_eq!(character.health, 50.0);
+
+        character.regenerate(at(3.0));
+        assert_eq!(character.health, 55.0);
+        assert!(matches!(receiver.try_iter().last(),
+                         Some(Message::Healed { amount, health, .. }) if amount == 5.0 && health == 55.0));
+
+        character.health = 98.0;
+        character.regenerate(at(4.0));
+        assert_eq!(character.health, 100.0);
+    }
+
+    #[test]
+    fn the_dead_do_not_regenerate() {
+        let mut character = Character {
+            regeneration: 10.0,
+            ..Default::default()
+        };
+
+        assert!(character.apply_damage(200.0, at(1.0)));
+        character.regenerate(at(10.0));
+
+        assert!(character.is_dead());
+        assert_eq!(character.health, 0.0);
+    }
+}
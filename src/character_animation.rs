@@ -1,74 +1,66 @@
 use crate::{
     GameTime,
+    animation_rule::Rule,
     character_body::CharacterBody,
+    settings::AnimationSettings,
+    resource_helper::{CharacterResources, AnimationResource},
+    error::{GameError, GameResult},
 };
 use rg3d::{
     core::pool::Handle,
-    resource::model::Model,
     animation::{
         machine, machine::Machine as AnimationMachine,
         Animation
     },
     scene::Scene,
 };
+use std::collections::HashMap;
 
 #[derive(Default)]
 pub struct CharacterAnimations {
-    pub idle: Handle<Animation>,
-    pub run: Handle<Animation>,
-    pub jump: Handle<Animation>,
-    pub attack: Handle<Animation>,
-}
-
-#[macro_export]
-macro_rules! character_animations {
-    ($scene:expr, $resource_manager:expr, $body:expr, $($name:ident).+, $settings:ident) => {
-        {
-            use crate::request_animation;
-            // TODO: do this concurrently
-            let idle = request_animation!($resource_manager, $($name).+.idle, $settings);
-            let run = request_animation!($resource_manager, $($name).+.run, $settings);
-            let jump = request_animation!($resource_manager, $($name).+.jump, $settings);
-            let attack = request_animation!($resource_manager, $($name).+.attack, $settings);
-
-            CharacterAnimations::new($scene, $body,
-                                     idle, run, jump, attack)
-        }
-    };
+    pub states: HashMap<String, Handle<Animation>>,
 }
 
 impl CharacterAnimations {
     pub fn new(scene: &mut Scene,
                body: &CharacterBody,
-               idle: Model,
-               run: Model,
-               jump: Model,
-               attack: Model) -> Self {
-        let idle = Self::prepare_animation(scene, body, idle);
-        let run = Self::prepare_animation(scene, body, run);
-        let jump = Self::prepare_animation(scene, body, jump);
-        let attack = Self::prepare_animation(scene, body, attack);
-
-        scene.animations.get_mut(jump).set_loop(false);
-
-        Self { 
-            idle,
-            run,
-            jump,
-            attack,
+               resources: &CharacterResources) -> GameResult<Self> {
+        let settings = &resources.animation_settings;
+
+        let mut states = HashMap::new();
+        for (state, resource) in resources.animations.iter() {
+            let animation = Self::prepare_animation(scene, body, resource)?;
+            scene.animations.get_mut(animation).set_loop(settings.states[state].looped);
+            states.insert(state.clone(), animation);
         }
+
+        Ok(Self { states })
     }
 
     fn prepare_animation(scene: &mut Scene,
                          body: &CharacterBody,
-                         animation: Model) -> Handle<Animation> {
-        let animation = animation.retarget_animations(body.model, scene)[0];
+                         resource: &AnimationResource) -> GameResult<Handle<Animation>> {
+        let animation = resource.model
+            .retarget_animations(body.model, scene)
+            .first()
+            .copied()
+            .ok_or_else(|| GameError::Retarget {
+                key: resource.file.key.clone(),
+                path: resource.file.path.clone(),
+            })?;
+
         scene
             .animations
             .get_mut(animation)
             .set_node_track_enabled(body.spine, false);
 
-        animation
+        Ok(animation)
+    }
+
+    pub fn clean_up(&self, scene: &mut Scene) {
+        for animation in self.states.values() {
+            scene.animations.remove(*animation);
+        }
     }
 }
 
@@ -82,191 +74,127 @@ pub struct CharacterAnimationInput {
     pub attacking: bool,
 }
 
+impl CharacterAnimationInput {
+    /// Names that can be used in animation rules.
+    pub const FLAGS: &'static [&'static str] = &[
+        "running",
+        "jumping",
+        "just_started_jumping",
+        "attacking",
+    ];
+
+    pub fn flag(&self, name: &str) -> bool {
+        match name {
+            "running" => self.running,
+            "jumping" => self.jumping,
+            "just_started_jumping" => self.just_started_jumping,
+            "attacking" => self.attacking,
+            _ => false,
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct CharacterAnimationController {
     pub animations: CharacterAnimations,
     pub machine: AnimationMachine,
+    /// Machine parameter and the rule that drives it, one per transition.
+    rules: Vec<(String, Rule)>,
+    rewinds: Vec<(Handle<Animation>, Rule)>,
 }
 
 impl CharacterAnimationController {
-    const WALK_TO_IDLE_PARAM: &'static str = "WalkToIdle";
-    const WALK_TO_JUMP_PARAM: &'static str = "WalkToJump";
-    const IDLE_TO_WALK_PARAM: &'static str = "IdleToWalk";
-    const IDLE_TO_JUMP_PARAM: &'static str = "IdleToJump";
-    const JUMP_TO_WALK_PARAM: &'static str = "JumpToIdle";
-    const JUMP_TO_IDLE_PARAM: &'static str = "JumpToWalk";
-
-    const WALK_TO_ATTACK_PARAM: &'static str = "WalkToAttack";
-    const IDLE_TO_ATTACK_PARAM: &'static str = "IdleToAttack";
-    const JUMP_TO_ATTACK_PARAM: &'static str = "JumpToAttack";
-    const ATTACK_TO_WALK_PARAM: &'static str = "AttackToWalk";
-    const ATTACK_TO_IDLE_PARAM: &'static str = "AttackToIdle";
-    const ATTACK_TO_JUMP_PARAM: &'static str = "AttackToJump";
-
-    pub fn new(animations: CharacterAnimations) -> Self {
+    /// `key` is where `settings` are found, used in error messages.
+    pub fn new(animations: CharacterAnimations,
+               settings: &AnimationSettings,
+               key: &str) -> GameResult<Self> {
         let mut machine = AnimationMachine::new();
 
-        // TODO: get rid of code duplication for every animation in several places
-        let idle_node = machine.add_node(machine::PoseNode::make_play_animation(animations.idle));
-        let idle_state = machine.add_state(machine::State::new("idle", idle_node));
-
-        let run_node = machine.add_node(machine::PoseNode::make_play_animation(animations.run));
-        let run_state = machine.add_state(machine::State::new("run", run_node));
-
-        let jump_node = machine.add_node(machine::PoseNode::make_play_animation(animations.jump));
-        let jump_state = machine.add_state(machine::State::new("jump", jump_node));
-
-        let attack_node = machine.add_node(machine::PoseNode::make_play_animation(animations.attack));
-        let attack_state = machine.add_state(machine::State::new("attack", attack_node));
+        let parse = |key: String, rule: &str| {
+            Rule::parse(rule, CharacterAnimationInput::FLAGS)
+                .map_err(|error| GameError::Animation { key, reason: error.to_string() })
+        };
+
+        let mut states = HashMap::new();
+        let mut rewinds = Vec::new();
+        for (state, &animation) in animations.states.iter() {
+            let node = machine.add_node(machine::PoseNode::make_play_animation(animation));
+            let handle = machine.add_state(machine::State::new(state, node));
+            states.insert(state.as_str(), handle);
+
+            if let Some(rewind) = &settings.states[state].rewind {
+                let key = format!("{}.states.{}.rewind", key, state);
+                rewinds.push((animation, parse(key, rewind)?));
+            }
+        }
 
-        machine.add_transition(machine::Transition::new(
-            "run->idle",
-            run_state,
-            idle_state,
-            0.5,
-            Self::WALK_TO_IDLE_PARAM,
-        ));
-        machine.add_transition(machine::Transition::new(
-            "run->jump",
-            run_state,
-            jump_state,
-            0.25,
-            Self::WALK_TO_JUMP_PARAM,
-        ));
-        machine.add_transition(machine::Transition::new(
-            "idle->run",
-            idle_state,
-            run_state,
-            0.1,
-            Self::IDLE_TO_WALK_PARAM,
-        ));
-        machine.add_transition(machine::Transition::new(
-            "idle->jump",
-            idle_state,
-            jump_state,
-            0.25,
-            Self::IDLE_TO_JUMP_PARAM,
-        ));
-        machine.add_transition(machine::Transition::new(
-            "jump->run",
-            jump_state,
-            run_state,
-            0.1,
-            Self::JUMP_TO_WALK_PARAM,
-        ));
-        machine.add_transition(machine::Transition::new(
-            "jump->idle",
-            jump_state,
-            idle_state,
-            0.5,
-            Self::JUMP_TO_IDLE_PARAM,
-        ));
+        let state = |key: String, state: &str| states
+            .get(state)
+            .copied()
+            .ok_or_else(|| GameError::Animation {
+                key,
+                reason: format!("state \"{}\" is not defined", state),
+            });
+
+        let mut rules = Vec::new();
+        for (i, transition) in settings.transitions.iter().enumerate() {
+            let transition_key = format!("{}.transitions[{}]", key, i);
+            let parameter = format!("{}->{}", transition.from, transition.to);
+
+            machine.add_transition(machine::Transition::new(
+                &parameter,
+                state(transition_key.clone(), &transition.from)?,
+                state(transition_key.clone(), &transition.to)?,
+                transition.blend,
+                &parameter,
+            ));
+
+            rules.push((parameter, parse(transition_key, &transition.rule)?));
+        }
 
-        machine.add_transition(machine::Transition::new(
-            "run->attack",
-            run_state,
-            attack_state,
-            0.1,
-            Self::WALK_TO_ATTACK_PARAM,
-        ));
-        machine.add_transition(machine::Transition::new(
-            "idle->attack",
-            idle_state,
-            attack_state,
-            0.1,
-            Self::IDLE_TO_ATTACK_PARAM,
-        ));
-        machine.add_transition(machine::Transition::new(
-            "jump->attack",
-            jump_state,
-            attack_state,
-            0.1,
-            Self::JUMP_TO_ATTACK_PARAM,
-        ));
-        machine.add_transition(machine::Transition::new(
-            "attack->run",
-            attack_state,
-            run_state,
-            0.1,
-            Self::ATTACK_TO_WALK_PARAM,
-        ));
-        machine.add_transition(machine::Transition::new(
-            "attack->idle",
-            attack_state,
-            idle_state,
-            0.1,
-            Self::ATTACK_TO_IDLE_PARAM,
-        ));
-        machine.add_transition(machine::Transition::new(
-            "attack->jump",
-            attack_state,
-            jump_state,
-            0.1,
-            Self::ATTACK_TO_JUMP_PARAM,
-        ));
-        machine.set_entry_state(idle_state);
+        let entry = state(format!("{}.entry", key), &settings.entry)?;
+        machine.set_entry_state(entry);
 
-        Self {
+        Ok(Self {
             animations,
             machine,
+            rules,
+            rewinds,
+        })
+    }
+
+    /// Plays the animations of `resources` on `body`. Bare characters get a
+    /// controller without states, which leaves their stand-in node alone.
+    pub fn from_resources(scene: &mut Scene,
+                          body: &CharacterBody,
+                          resources: &CharacterResources) -> GameResult<Self> {
+        if resources.model.is_none() {
+            return Ok(Self::default());
         }
+
+        let animations = CharacterAnimations::new(scene, body, resources)?;
+        Self::new(animations, &resources.animation_settings, &resources.animations_key)
     }
 
     pub fn apply(&mut self, scene: &mut Scene, time: GameTime, input: CharacterAnimationInput) {
-        if input.just_started_jumping {
-            scene.animations.get_mut(self.animations.jump).rewind();
+        let flag = |name: &str| input.flag(name);
+
+        for (animation, rule) in self.rewinds.iter() {
+            if rule.evaluate(&flag) {
+                scene.animations.get_mut(*animation).rewind();
+            }
+        }
+
+        for (parameter, rule) in self.rules.iter() {
+            self.machine.set_parameter(parameter, machine::Parameter::Rule(rule.evaluate(&flag)));
         }
 
         self.machine
-            .set_parameter(
-                Self::IDLE_TO_WALK_PARAM,
-                machine::Parameter::Rule(input.running),
-            )
-            .set_parameter(
-                Self::WALK_TO_IDLE_PARAM,
-                machine::Parameter::Rule(!input.running),
-            )
-            .set_parameter(
-                Self::WALK_TO_JUMP_PARAM,
-                machine::Parameter::Rule(input.jumping),
-            )
-            .set_parameter(
-                Self::IDLE_TO_JUMP_PARAM,
-                machine::Parameter::Rule(input.jumping),
-            )
-            .set_parameter(
-                Self::JUMP_TO_WALK_PARAM,
-                machine::Parameter::Rule(input.running && !input.jumping),
-            )
-            .set_parameter(
-                Self::JUMP_TO_IDLE_PARAM,
-                machine::Parameter::Rule(!input.running && !input.jumping),
-            )
-            .set_parameter(
-                Self::WALK_TO_ATTACK_PARAM,
-                machine::Parameter::Rule(input.attacking),
-            )
-            .set_parameter(
-                Self::IDLE_TO_ATTACK_PARAM,
-                machine::Parameter::Rule(input.attacking),
-            )
-            .set_parameter(
-                Self::JUMP_TO_ATTACK_PARAM,
-                machine::Parameter::Rule(!input.jumping && input.attacking),
-            )
-            .set_parameter(
-                Self::ATTACK_TO_WALK_PARAM,
-                machine::Parameter::Rule(!input.attacking && input.running),
-            )
-            .set_parameter(
-                Self::ATTACK_TO_IDLE_PARAM,
-                machine::Parameter::Rule(!input.attacking && !input.running),
-            )
-            .set_parameter(
-                Self::ATTACK_TO_JUMP_PARAM,
-                machine::Parameter::Rule(!input.attacking && input.jumping),
-            )
             .evaluate_pose(&scene.animations, time.delta)
             .apply(&mut scene.graph);
     }
+
+    pub fn clean_up(&self, scene: &mut Scene) {
+        self.animations.clean_up(scene);
+    }
 }
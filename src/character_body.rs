@@ -15,7 +15,11 @@ use rg3d::{
     },
     resource::model::Model,
 };
-use crate::settings::CharacterSize;
+use crate::{
+    settings::CharacterSize,
+    resource_helper::CharacterResources,
+    error::{GameError, GameResult},
+};
 
 #[derive(Default)]
 pub struct CharacterBody {
@@ -28,8 +32,9 @@ pub struct CharacterBody {
 }
 
 impl CharacterBody {
+    /// Without a `model`, an empty node stands in for it and there is no spine.
     pub fn new(scene: &mut Scene, 
-                 model: Model,
+                 model: Option<Model>,
                  spine: String,
                  size: CharacterSize, scale: f32,
                  position: Vector3<f32>) -> Self {
@@ -41,7 +46,10 @@ impl CharacterBody {
             .build(),
             );
 
-        let model = model.instantiate_geometry(scene);
+        let model = match model {
+            Some(model) => model.instantiate_geometry(scene),
+            None => BaseBuilder::new().build(&mut scene.graph),
+        };
 
         let pivot = BaseBuilder::new()
             .with_children(&[model])
@@ -72,6 +80,27 @@ impl CharacterBody {
         }
     }
 
+    pub fn from_resources(scene: &mut Scene,
+                          resources: &CharacterResources,
+                          position: Vector3<f32>) -> GameResult<Self> {
+        let settings = &resources.model_settings;
+        let body = Self::new(scene,
+                             resources.model.clone(),
+                             settings.spine.clone(),
+                             settings.size, settings.scale,
+                             position);
+
+        if resources.model.is_some() && body.spine.is_none() {
+            return Err(GameError::MissingNode {
+                key: format!("{}.spine", resources.model_key),
+                node: settings.spine.clone(),
+                model: settings.model.clone(),
+            });
+        }
+
+        Ok(body)
+    }
+
     pub fn has_ground_contact(&self, physics: &Physics) -> bool {
         let body = physics.bodies.get(&self.body).unwrap();
         for contact in physics.narrow_phase.contacts_with(body.colliders()[0]) {
@@ -84,21 +113,3 @@ impl CharacterBody {
         false
     }
 }
-
-#[macro_export]
-macro_rules! character_body {
-    ($resource_manager:ident, $scene:expr, $($name:ident).+, $position:expr) => {
-        {
-            use crate::SETTINGS;
-            let settings = &SETTINGS.read().unwrap();
-            let models = &settings.models;
-            let spine = models.$($name).+.spine.clone();
-            let size = models.$($name).+.size;
-            let scale = models.$($name).+.scale;
-
-            let model = request_model!($resource_manager, $($name).+.model, settings);
-            CharacterBody::new($scene, model, spine, size, scale, $position)
-        }
-    };
-}
-
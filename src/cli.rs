@@ -0,0 +1,68 @@
+use crate::error::{GameError, GameResult};
+use std::{env, path::PathBuf};
+
+#[derive(Debug, Default)]
+pub struct Options {
+    /// Number of fixed ticks to simulate without a window.
+    pub headless: Option<u64>,
+    /// Overrides `seed` from the settings.
+    pub seed: Option<u64>,
+    /// File to record input events to.
+    pub record: Option<PathBuf>,
+    /// File to replay input events from instead of live input.
+    pub replay: Option<PathBuf>,
+    /// Validate the settings, print every problem found and exit.
+    pub check_settings: bool,
+    /// Print the settings merged from all layers and exit.
+    pub print_settings: bool,
+    /// `key=value` pairs from `--set`, overriding every other settings layer.
+    pub overrides: Vec<(String, String)>,
+}
+
+impl Options {
+    pub fn parse() -> GameResult<Self> {
+        Self::parse_from(env::args().skip(1))
+    }
+
+    pub fn parse_from<I: Iterator<Item = String>>(mut args: I) -> GameResult<Self> {
+        let mut options = Self::default();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--headless" => {
+                    let ticks = value(&mut args, &arg)?;
+                    options.headless = Some(ticks.parse().map_err(|_| {
+                        GameError::Usage(format!("--headless expects a number of ticks, got \"{}\"", ticks))
+                    })?);
+                }
+                "--seed" => {
+                    let seed = value(&mut args, &arg)?;
+                    options.seed = Some(seed.parse().map_err(|_| {
+                        GameError::Usage(format!("--seed expects a number, got \"{}\"", seed))
+                    })?);
+                }
+                "--record" => options.record = Some(value(&mut args, &arg)?.into()),
+                "--replay" => options.replay = Some(value(&mut args, &arg)?.into()),
+                "--check-settings" => options.check_settings = true,
+                "--print-settings" => options.print_settings = true,
+                "--set" => {
+                    let assignment = value(&mut args, &arg)?;
+                    let (key, value) = assignment.split_once('=')
+                        .filter(|(key, _)| !key.is_empty())
+                        .ok_or_else(|| {
+                            GameError::Usage(format!("--set expects key=value, got \"{}\"", assignment))
+                        })?;
+                    options.overrides.push((key.to_owned(), value.to_owned()));
+                }
+                _ => return Err(GameError::Usage(format!("unknown argument \"{}\"", arg))),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+fn value<I: Iterator<Item = String>>(args: &mut I, flag: &str) -> GameResult<String> {
+    args.next()
+        .ok_or_else(|| GameError::Usage(format!("{} expects a value", flag)))
+}
@@ -0,0 +1,410 @@
+use crate::{
+    keyboard_input::{Action, Binding, GamepadSettings, Input, InputEvent, KeyMap, Modifiers},
+    gamepad::GamepadAxis,
+};
+use rg3d::{
+    core::algebra::Vector2,
+    event::VirtualKeyCode,
+};
+use std::collections::{HashMap, HashSet};
+
+/// State of an action during a tick.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ActionState {
+    pub held: bool,
+    /// Became held during this tick.
+    pub pressed: bool,
+    /// Stopped being held during this tick.
+    pub released: bool,
+    /// Pressed within `double_tap_interval` of the previous press.
+    pub double_tapped: bool,
+}
+
+/// Turns input events into the state of every action, updated once per tick.
+pub struct Controls {
+    keymap: KeyMap,
+    /// Inputs that are down right now.
+    down: HashSet<Input>,
+    /// Inputs pressed since the last update, so that a press and a release
+    /// between two ticks is not lost.
+    tapped: HashSet<Input>,
+    states: HashMap<Action, ActionState>,
+    last_press: HashMap<Action, f64>,
+    axes: HashMap<GamepadAxis, f32>,
+}
+
+impl Controls {
+    pub fn new(keymap: KeyMap) -> Self {
+        Self {
+            keymap,
+            down: Default::default(),
+            tapped: Default::default(),
+            states: Default::default(),
+            last_press: Default::default(),
+            axes: Default::default(),
+        }
+    }
+
+    pub fn apply_settings(&mut self, keymap: KeyMap) {
+        self.keymap = keymap;
+    }
+
+    pub fn process_input_event(&mut self, event: &InputEvent) {
+        let (input, pressed) = match *event {
+            InputEvent::Key { code, pressed } => (Input::Key(code), pressed),
+            InputEvent::MouseButton { button, pressed } => (Input::Mouse(button), pressed),
+            InputEvent::Wheel { delta } if delta > 0.0 => (Input::WheelUp, true),
+            InputEvent::Wheel { .. } => (Input::WheelDown, true),
+            InputEvent::GamepadButton { button, pressed } => (Input::Gamepad(button), pressed),
+            InputEvent::GamepadAxis { axis, value } => {
+                self.axes.insert(axis, value);
+                match axis.trigger_button() {
+                    Some(button) => (Input::Gamepad(button), value > self.keymap.gamepad.trigger_threshold),
+                    None => return,
+                }
+            }
+            InputEvent::ReleaseAll => {
+                self.clear();
+                return;
+            }
+            InputEvent::MouseMotion { .. } => return,
+        };
+
+        if pressed && self.down.contains(&input) {
+            // Key repeats and trigger movements while the input is held.
+            return;
+        }
+
+        if pressed {
+            // Wheel notches are never released, so they are not kept down.
+            if !matches!(input, Input::WheelUp | Input::WheelDown) {
+                self.down.insert(input);
+            }
+            self.tapped.insert(input);
+        } else {
+            self.down.remove(&input);
+        }
+    }
+
+    /// Computes the state of every action for the tick at `time`.
+    pub fn update(&mut self, time: f64) {
+        let active = self.active_bindings();
+
+        for &action in Action::ALL {
+            let bindings = self.keymap.bindings.get(&action).map_or(&[][..], Vec::as_slice);
+            let held = bindings.iter().any(|binding| active.contains(binding));
+            let repressed = bindings.iter()
+                .any(|binding| active.contains(binding) && self.tapped.contains(&binding.input));
+
+            let previous = self.state(action);
+            let pressed = held && (!previous.held || repressed);
+
+            let mut double_tapped = false;
+            if pressed {
+                let interval = self.keymap.double_tap_interval as f64;
+                match self.last_press.get(&action) {
+                    Some(&last) if time - last <= interval => {
+                        double_tapped = true;
+                        // A third press starts a new double tap.
+                        self.last_press.remove(&action);
+                    }
+                    _ => {
+                        self.last_press.insert(action, time);
+                    }
+                }
+            }
+
+            self.states.insert(action, ActionState {
+                held,
+                pressed,
+                released: previous.held && !held,
+                double_tapped,
+            });
+        }
+
+        self.tapped.clear();
+    }
+
+    pub fn state(&self, action: Action) -> ActionState {
+        self.states.get(&action).copied().unwrap_or_default()
+    }
+
+    pub fn held(&self, action: Action) -> bool {
+        self.state(action).held
+    }
+
+    pub fn pressed(&self, action: Action) -> bool {
+        self.state(action).pressed
+    }
+
+    /// Direction to move in, x to the right and y forward, no longer than 1.
+    /// Movement keys push all the way, the left stick as far as it is pushed.
+    pub fn movement(&self) -> Vector2<f32> {
+        let axis = |positive, negative| self.held(positive) as i32 as f32 - self.held(negative) as i32 as f32;
+        let keys = Vector2::new(axis(Action::Right, Action::Left), axis(Action::Forward, Action::Backward));
+
+        let movement = keys + self.stick(GamepadAxis::LeftStickX, GamepadAxis::LeftStickY);
+        if movement.norm() > 1.0 {
+            movement.normalize()
+        } else {
+            movement
+        }
+    }
+
+    /// Camera turn speed from the right stick, x to the right and y up, in degrees per second.
+    pub fn look(&self) -> Vector2<f32> {
+        self.stick(GamepadAxis::RightStickX, GamepadAxis::RightStickY) * self.keymap.gamepad.look_speed
+    }
+
+    /// Forgets all inputs, e.g. when the controls are disabled.
+    pub fn clear(&mut self) {
+        self.down.clear();
+        self.tapped.clear();
+        self.states.clear();
+        self.last_press.clear();
+        self.axes.clear();
+    }
+
+    fn stick(&self, x: GamepadAxis, y: GamepadAxis) -> Vector2<f32> {
+        let axis = |axis| self.axes.get(&axis).copied().unwrap_or_default();
+        deadzone(Vector2::new(axis(x), axis(y)), &self.keymap.gamepad)
+    }
+
+    fn modifiers(&self) -> Modifiers {
+        let down = |codes: [VirtualKeyCode; 2]| codes.iter().any(|code| self.down.contains(&Input::Key(*code)));
+
+        Modifiers {
+            ctrl: down([VirtualKeyCode::LControl, VirtualKeyCode::RControl]),
+            shift: down([VirtualKeyCode::LShift, VirtualKeyCode::RShift]),
+            alt: down([VirtualKeyCode::LAlt, VirtualKeyCode::RAlt]),
+        }
+    }
+
+    /// Bindings whose input is down or was tapped and whose modifiers are held.
+    /// When several bindings share an input, only those with the most modifiers
+    /// are active, so that `Ctrl+S` does not trigger the action bound to `S`.
+    fn active_bindings(&self) -> HashSet<Binding> {
+        let modifiers = self.modifiers();
+
+        let mut candidates = HashMap::<Input, Vec<Binding>>::new();
+        for binding in self.keymap.bindings.values().flatten() {
+            let input_active = self.down.contains(&binding.input) || self.tapped.contains(&binding.input);
+            if input_active && binding.modifiers.held_in(modifiers) {
+                candidates.entry(binding.input).or_default().push(*binding);
+            }
+        }
+
+        candidates
+            .into_values()
+            .flat_map(|bindings| {
+                let most = bindings.iter().map(|binding| binding.modifiers.count()).max().unwrap_or(0);
+                bindings.into_iter().filter(move |binding| binding.modifiers.count() == most)
+            })
+            .collect()
+    }
+}
+
+/// Zeroes `stick` inside the deadzone and rescales the rest, so that the
+/// deflection grows from 0 at the deadzone to 1 at the outer deadzone.
+fn deadzone(stick: Vector2<f32>, settings: &GamepadSettings) -> Vector2<f32> {
+    let deflection = stick.norm();
+    if deflection <= settings.deadzone {
+        return Vector2::zeros();
+    }
+
+    let range = (settings.outer_deadzone - settings.deadzone).max(f32::EPSILON);
+    let scaled = ((deflection - settings.deadzone) / range).min(1.0);
+    stick * (scaled / deflection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{gamepad::{GamepadBackend, GamepadButton}, keyboard_input::key};
+    use rg3d::event::MouseButton;
+    use std::{collections::VecDeque, convert::TryFrom};
+
+    /// Returns events queued up front, one batch per poll.
+    #[derive(Default)]
+    struct ScriptedGamepad {
+        events: VecDeque<Vec<InputEvent>>,
+    }
+
+    impl GamepadBackend for ScriptedGamepad {
+        fn poll(&mut self) -> Vec<InputEvent> {
+            self.events.pop_front().unwrap_or_default()
+        }
+    }
+
+    impl ScriptedGamepad {
+        fn axes(&mut self, values: &[(GamepadAxis, f32)]) {
+            self.events.push_back(values.iter()
+                .map(|&(axis, value)| InputEvent::GamepadAxis { axis, value })
+                .collect());
+        }
+    }
+
+    fn tick(controls: &mut Controls, gamepad: &mut dyn GamepadBackend, time: f64) {
+        for event in gamepad.poll() {
+            controls.process_input_event(&event);
+        }
+        controls.update(time);
+    }
+
+    fn controls(bindings: &[(Action, &str)]) -> Controls {
+        Controls::new(KeyMap::with_bindings(bindings))
+    }
+
+    #[test]
+    fn press_hold_and_release() {
+        let mut controls = controls(&[(Action::Jump, "Space")]);
+
+        controls.process_input_event(&key(VirtualKeyCode::Space, true));
+        controls.update(0.0);
+        assert_eq!(controls.state(Action::Jump), ActionState { held: true, pressed: true, ..Default::default() });
+
+        controls.update(0.1);
+        assert_eq!(controls.state(Action::Jump), ActionState { held: true, ..Default::default() });
+
+        controls.process_input_event(&key(VirtualKeyCode::Space, false));
+        controls.update(0.2);
+        assert_eq!(controls.state(Action::Jump), ActionState { released: true, ..Default::default() });
+    }
+
+    #[test]
+    fn taps_shorter_than_a_tick_are_seen() {
+        let mut controls = controls(&[(Action::Attack, "MouseLeft"), (Action::Jump, "WheelUp")]);
+
+        controls.process_input_event(&InputEvent::MouseButton { button: MouseButton::Left, pressed: true });
+        controls.process_input_event(&InputEvent::MouseButton { button: MouseButton::Left, pressed: false });
+        controls.process_input_event(&InputEvent::Wheel { delta: 1.0 });
+        controls.update(0.0);
+        assert!(controls.pressed(Action::Attack));
+        assert!(controls.pressed(Action::Jump));
+
+        controls.update(0.1);
+        assert!(controls.state(Action::Attack).released);
+        assert!(controls.state(Action::Jump).released);
+    }
+
+    #[test]
+    fn double_tap_within_interval() {
+        let mut controls = controls(&[(Action::Forward, "W")]);
+        let mut tap = |time| {
+            controls.process_input_event(&key(VirtualKeyCode::W, true));
+            controls.process_input_event(&key(VirtualKeyCode::W, false));
+            controls.update(time);
+            controls.state(Action::Forward).double_tapped
+        };
+
+        assert!(!tap(0.0));
+        assert!(tap(0.2));
+        assert!(!tap(0.3));
+        assert!(!tap(1.0));
+    }
+
+    #[test]
+    fn chords_take_precedence() {
+        let mut controls = controls(&[(Action::Backward, "S"), (Action::Jump, "Ctrl+S")]);
+
+        controls.process_input_event(&key(VirtualKeyCode::LControl, true));
+        controls.process_input_event(&key(VirtualKeyCode::S, true));
+        controls.update(0.0);
+        assert!(controls.held(Action::Jump));
+        assert!(!controls.held(Action::Backward));
+
+        controls.process_input_event(&key(VirtualKeyCode::LControl, false));
+        controls.update(0.1);
+        assert!(controls.state(Action::Jump).released);
+        assert!(controls.pressed(Action::Backward));
+    }
+
+    #[test]
+    fn bindings_round_trip() {
+        for source in &["W", "Ctrl+Shift+S", "Alt+MouseLeft", "MouseOther4", "WheelDown"] {
+            let binding = Binding::try_from(source.to_string()).unwrap();
+            assert_eq!(binding.to_string(), *source);
+        }
+
+        assert!(Binding::try_from("Hyper+W".to_owned()).is_err());
+        assert!(Binding::try_from("NoSuchKey".to_owned()).is_err());
+    }
+
+    #[test]
+    fn release_all_lets_go_of_held_inputs() {
+        let mut controls = controls(&[(Action::Forward, "W")]);
+
+        controls.process_input_event(&key(VirtualKeyCode::W, true));
+        controls.update(0.0);
+        controls.process_input_event(&InputEvent::ReleaseAll);
+        controls.update(0.1);
+
+        assert!(!controls.held(Action::Forward));
+    }
+
+    #[test]
+    fn key_repeats_are_not_presses() {
+        let mut controls = controls(&[(Action::Jump, "Space")]);
+
+        controls.process_input_event(&key(VirtualKeyCode::Space, true));
+        controls.update(0.0);
+        controls.process_input_event(&key(VirtualKeyCode::Space, true));
+        controls.update(0.1);
+
+        assert_eq!(controls.state(Action::Jump), ActionState { held: true, ..Default::default() });
+    }
+
+    #[test]
+    fn stick_deflection_sets_movement_magnitude() {
+        let mut controls = controls(&[(Action::Forward, "W")]);
+        let mut gamepad = ScriptedGamepad::default();
+
+        gamepad.axes(&[(GamepadAxis::LeftStickX, 0.1), (GamepadAxis::LeftStickY, 0.1)]);
+        tick(&mut controls, &mut gamepad, 0.0);
+        assert_eq!(controls.movement(), Vector2::zeros());
+
+        gamepad.axes(&[(GamepadAxis::LeftStickX, 0.0), (GamepadAxis::LeftStickY, 0.55)]);
+        tick(&mut controls, &mut gamepad, 0.1);
+        assert!((controls.movement() - Vector2::new(0.0, 0.5)).norm() < 1e-5);
+
+        gamepad.axes(&[(GamepadAxis::LeftStickY, 1.0)]);
+        tick(&mut controls, &mut gamepad, 0.2);
+        assert!((controls.movement() - Vector2::new(0.0, 1.0)).norm() < 1e-5);
+
+        // Keys and the stick together never go past full speed.
+        controls.process_input_event(&key(VirtualKeyCode::W, true));
+        tick(&mut controls, &mut gamepad, 0.3);
+        assert!((controls.movement().norm() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn right_stick_turns_the_camera() {
+        let mut controls = controls(&[]);
+        let mut gamepad = ScriptedGamepad::default();
+
+        gamepad.axes(&[(GamepadAxis::RightStickX, -0.9), (GamepadAxis::RightStickY, 0.0)]);
+        tick(&mut controls, &mut gamepad, 0.0);
+
+        assert!((controls.look() - Vector2::new(-100.0, 0.0)).norm() < 1e-3);
+    }
+
+    #[test]
+    fn triggers_and_buttons_drive_actions() {
+        let mut controls = controls(&[(Action::Attack, "PadRightTrigger"), (Action::Jump, "PadSouth")]);
+        let mut gamepad = ScriptedGamepad::default();
+
+        gamepad.axes(&[(GamepadAxis::RightTrigger, 0.3)]);
+        tick(&mut controls, &mut gamepad, 0.0);
+        assert!(!controls.held(Action::Attack));
+
+        gamepad.axes(&[(GamepadAxis::RightTrigger, 0.6)]);
+        gamepad.events.push_back(vec![InputEvent::GamepadButton { button: GamepadButton::South, pressed: true }]);
+        tick(&mut controls, &mut gamepad, 0.1);
+        assert!(controls.pressed(Action::Attack));
+
+        gamepad.axes(&[(GamepadAxis::RightTrigger, 0.8)]);
+        tick(&mut controls, &mut gamepad, 0.2);
+        assert_eq!(controls.state(Action::Attack), ActionState { held: true, ..Default::default() });
+        assert!(controls.pressed(Action::Jump));
+    }
+}
@@ -0,0 +1,178 @@
+use crate::{
+    Gui, UiNode,
+    settings::SettingsSources,
+    keyboard_input::{Action, InputEvent, KeyMap},
+    rebinding::{Mode, Outcome, Rebinder},
+};
+use rg3d::{
+    core::pool::Handle,
+    event::VirtualKeyCode,
+    gui::{
+        message::{MessageDirection, TextMessage},
+        text::TextBuilder,
+        widget::WidgetBuilder,
+        HorizontalAlignment, Thickness,
+    },
+    utils::log::{Log, MessageKind},
+};
+
+const TOGGLE: VirtualKeyCode = VirtualKeyCode::F1;
+
+const HELP: &str = "Up/Down: select, Enter: replace, Tab: add, Delete: remove last, \
+                    Backspace: reset, F5: reset all, F1: close";
+
+/// Lists the bindings of every action and lets the player change them.
+/// Changes are saved to the user keymap, which the settings watcher picks up.
+pub struct ControlsMenu {
+    text: Handle<UiNode>,
+    sources: SettingsSources,
+    /// Set while the menu is open.
+    rebinder: Option<Rebinder>,
+    selected: usize,
+    status: String,
+    dirty: bool,
+}
+
+impl ControlsMenu {
+    pub fn new(ui: &mut Gui, sources: SettingsSources) -> Self {
+        let text = TextBuilder::new(WidgetBuilder::new()
+                                    .with_margin(Thickness::uniform(10.0))
+                                    .with_horizontal_alignment(HorizontalAlignment::Right))
+            .build(&mut ui.build_ctx());
+
+        Self {
+            text,
+            sources,
+            rebinder: None,
+            selected: 0,
+            status: String::new(),
+            dirty: false,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.rebinder.is_some()
+    }
+
+    /// Returns true if the menu took `event`, so that gameplay must not see it.
+    pub fn process_input_event(&mut self, event: &InputEvent, keymap: &KeyMap) -> bool {
+        let rebinder = match &mut self.rebinder {
+            Some(rebinder) => rebinder,
+            None => {
+                if let InputEvent::Key { code: TOGGLE, pressed: true } = event {
+                    self.open(keymap.clone());
+                    return true;
+                }
+                return false;
+            }
+        };
+        self.dirty = true;
+
+        if rebinder.waiting().is_some() {
+            match rebinder.process_input_event(event) {
+                Some(Outcome::Bound { action, binding }) => {
+                    self.status = format!("{} bound to {:?}. {}", binding, action, self.save());
+                }
+                Some(Outcome::Conflict { binding, actions }) => {
+                    self.status = format!("{} is already bound to {:?}, press another input", binding, actions);
+                }
+                Some(Outcome::Cancelled) => self.status.clear(),
+                None => (),
+            }
+            return true;
+        }
+
+        let code = match event {
+            InputEvent::Key { code, pressed: true } => *code,
+            _ => return true,
+        };
+        let action = Action::ALL[self.selected];
+
+        match code {
+            TOGGLE | VirtualKeyCode::Escape => self.close(),
+            VirtualKeyCode::Up => self.selected = (self.selected + Action::ALL.len() - 1) % Action::ALL.len(),
+            VirtualKeyCode::Down => self.selected = (self.selected + 1) % Action::ALL.len(),
+            VirtualKeyCode::Return | VirtualKeyCode::Tab => {
+                let mode = if code == VirtualKeyCode::Tab { Mode::Add } else { Mode::Replace };
+                rebinder.start(action, mode);
+                self.status = format!("Press the input for {:?}, Escape to cancel", action);
+            }
+            VirtualKeyCode::Delete => {
+                if rebinder.remove_last(action).is_some() {
+                    self.status = self.save();
+                } else {
+                    self.status = format!("{:?} needs at least one binding", action);
+                }
+            }
+            VirtualKeyCode::Back => {
+                rebinder.reset(action);
+                self.status = self.save();
+            }
+            VirtualKeyCode::F5 => {
+                rebinder.reset_all();
+                self.status = self.save();
+            }
+            _ => (),
+        }
+
+        true
+    }
+
+    pub fn update(&mut self, ui: &mut Gui) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+
+        let text = match &self.rebinder {
+            Some(rebinder) => {
+                let mut text = format!("Controls\n{}\n", HELP);
+                for (i, action) in Action::ALL.iter().enumerate() {
+                    let bindings = rebinder.bindings(*action)
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    let cursor = if i == self.selected { ">" } else { " " };
+                    text += &format!("\n{} {:?}: {}", cursor, action, bindings);
+                }
+                text + "\n\n" + &self.status
+            }
+            None => String::new(),
+        };
+
+        ui.send_message(TextMessage::text(self.text, MessageDirection::ToWidget, text));
+    }
+
+    fn open(&mut self, keymap: KeyMap) {
+        let defaults = match self.sources.default_keymap() {
+            Ok(defaults) => defaults,
+            Err(error) => {
+                Log::writeln(MessageKind::Error, format!("Failed to read the default keymap: {}", error));
+                keymap.clone()
+            }
+        };
+
+        self.rebinder = Some(Rebinder::new(keymap, defaults));
+        self.status.clear();
+        self.dirty = true;
+    }
+
+    fn close(&mut self) {
+        self.rebinder = None;
+        self.dirty = true;
+    }
+
+    /// Returns the status to show.
+    fn save(&self) -> String {
+        let rebinder = self.rebinder.as_ref().unwrap();
+
+        match &self.sources.user_keymap {
+            Some(path) => match rebinder.save(path) {
+                Ok(()) => format!("Saved to {}", path.display()),
+                Err(error) => error.to_string(),
+            },
+            None => "No user directory to save the keymap to".to_owned(),
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use crate::validation::Problem;
+use config::ConfigError;
+use std::{
+    fmt,
+    path::PathBuf,
+};
+
+#[derive(Debug)]
+pub enum GameError {
+    Settings(ConfigError),
+    ResourceLoad {
+        key: String,
+        path: PathBuf,
+        reason: String,
+    },
+    MissingNode {
+        key: String,
+        node: String,
+        model: String,
+    },
+    Retarget {
+        key: String,
+        path: PathBuf,
+    },
+    Animation {
+        key: String,
+        reason: String,
+    },
+    Behavior {
+        key: String,
+        reason: String,
+    },
+    Invalid {
+        key: String,
+        reason: String,
+    },
+    /// `name` is referenced at `key`, but not defined.
+    Undefined {
+        key: String,
+        name: String,
+    },
+    Recording {
+        path: PathBuf,
+        reason: String,
+    },
+    /// A file written by the game, such as the user keymap.
+    Save {
+        path: PathBuf,
+        reason: String,
+    },
+    /// Problems found by `validation::validate`, all at once.
+    Validation(Vec<Problem>),
+    Engine(String),
+    Usage(String),
+}
+
+pub type GameResult<T> = Result<T, GameError>;
+
+impl fmt::Display for GameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GameError::Settings(error) => write!(f, "invalid settings: {}", error),
+            GameError::ResourceLoad { key, path, reason } => {
+                write!(f, "failed to load {} (settings key `{}`): {}", path.display(), key, reason)
+            }
+            GameError::MissingNode { key, node, model } => {
+                write!(f, "node \"{}\" not found in model {} (settings key `{}`)", node, model, key)
+            }
+            GameError::Retarget { key, path } => {
+                write!(f, "{} contains no animation that can be retargeted (settings key `{}`)",
+                       path.display(), key)
+            }
+            GameError::Animation { key, reason } => {
+                write!(f, "invalid animation setup (settings key `{}`): {}", key, reason)
+            }
+            GameError::Behavior { key, reason } => {
+                write!(f, "invalid behavior tree (settings key `{}`): {}", key, reason)
+            }
+            GameError::Invalid { key, reason } => {
+                write!(f, "invalid value (settings key `{}`): {}", key, reason)
+            }
+            GameError::Undefined { key, name } => {
+                write!(f, "\"{}\" is not defined (settings key `{}`)", name, key)
+            }
+            GameError::Recording { path, reason } => {
+                write!(f, "input recording {}: {}", path.display(), reason)
+            }
+            GameError::Save { path, reason } => {
+                write!(f, "failed to save {}: {}", path.display(), reason)
+            }
+            GameError::Validation(problems) => {
+                write!(f, "{} problem(s) found in settings:", problems.len())?;
+                for problem in problems {
+                    write!(f, "\n  {}", problem)?;
+                }
+                Ok(())
+            }
+            GameError::Engine(reason) => write!(f, "failed to initialize the engine: {}", reason),
+            GameError::Usage(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for GameError {}
+
+impl From<ConfigError> for GameError {
+    fn from(error: ConfigError) -> Self {
+        GameError::Settings(error)
+    }
+}
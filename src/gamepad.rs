@@ -0,0 +1,180 @@
+use crate::keyboard_input::InputEvent;
+use rg3d::utils::log::{Log, MessageKind};
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, Copy)]
+pub enum GamepadButton {
+    South,
+    East,
+    North,
+    West,
+    LeftBumper,
+    RightBumper,
+    /// Pressed when the trigger axis goes past `trigger_threshold`.
+    LeftTrigger,
+    RightTrigger,
+    Select,
+    Start,
+    LeftThumb,
+    RightThumb,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+impl GamepadButton {
+    pub const ALL: &'static [GamepadButton] = &[
+        GamepadButton::South,
+        GamepadButton::East,
+        GamepadButton::North,
+        GamepadButton::West,
+        GamepadButton::LeftBumper,
+        GamepadButton::RightBumper,
+        GamepadButton::LeftTrigger,
+        GamepadButton::RightTrigger,
+        GamepadButton::Select,
+        GamepadButton::Start,
+        GamepadButton::LeftThumb,
+        GamepadButton::RightThumb,
+        GamepadButton::DPadUp,
+        GamepadButton::DPadDown,
+        GamepadButton::DPadLeft,
+        GamepadButton::DPadRight,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|button| format!("{:?}", button) == name)
+    }
+}
+
+/// Stick axes go from -1 to 1, up and right being positive; triggers go from 0 to 1.
+#[derive(Debug, Hash, PartialEq, Eq, Clone, Copy)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+impl GamepadAxis {
+    pub const ALL: &'static [GamepadAxis] = &[
+        GamepadAxis::LeftStickX,
+        GamepadAxis::LeftStickY,
+        GamepadAxis::RightStickX,
+        GamepadAxis::RightStickY,
+        GamepadAxis::LeftTrigger,
+        GamepadAxis::RightTrigger,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|axis| format!("{:?}", axis) == name)
+    }
+
+    /// The button a trigger axis acts as.
+    pub fn trigger_button(self) -> Option<GamepadButton> {
+        match self {
+            GamepadAxis::LeftTrigger => Some(GamepadButton::LeftTrigger),
+            GamepadAxis::RightTrigger => Some(GamepadButton::RightTrigger),
+            _ => None,
+        }
+    }
+}
+
+/// Source of gamepad input, polled once per frame.
+pub trait GamepadBackend {
+    /// Returns the events that happened since the last poll.
+    fn poll(&mut self) -> Vec<InputEvent>;
+}
+
+/// Used when no gamepad library is available.
+pub struct NoGamepad;
+
+impl GamepadBackend for NoGamepad {
+    fn poll(&mut self) -> Vec<InputEvent> {
+        Vec::new()
+    }
+}
+
+pub struct GilrsBackend {
+    gilrs: gilrs::Gilrs,
+}
+
+impl GilrsBackend {
+    /// Falls back to `NoGamepad` if gamepads are not supported on this platform.
+    pub fn create() -> Box<dyn GamepadBackend> {
+        match gilrs::Gilrs::new() {
+            Ok(gilrs) => Box::new(Self { gilrs }),
+            Err(error) => {
+                Log::writeln(MessageKind::Warning, format!("Gamepads are not available: {}", error));
+                Box::new(NoGamepad)
+            }
+        }
+    }
+}
+
+impl GamepadBackend for GilrsBackend {
+    fn poll(&mut self) -> Vec<InputEvent> {
+        use gilrs::EventType;
+
+        let mut events = Vec::new();
+        while let Some(event) = self.gilrs.next_event() {
+            let event = match event.event {
+                EventType::ButtonPressed(button, _) => button_from_gilrs(button)
+                    .map(|button| InputEvent::GamepadButton { button, pressed: true }),
+                EventType::ButtonReleased(button, _) => button_from_gilrs(button)
+                    .map(|button| InputEvent::GamepadButton { button, pressed: false }),
+                // Analog triggers report their travel as a button value.
+                EventType::ButtonChanged(gilrs::Button::LeftTrigger2, value, _) => {
+                    Some(InputEvent::GamepadAxis { axis: GamepadAxis::LeftTrigger, value })
+                }
+                EventType::ButtonChanged(gilrs::Button::RightTrigger2, value, _) => {
+                    Some(InputEvent::GamepadAxis { axis: GamepadAxis::RightTrigger, value })
+                }
+                EventType::AxisChanged(axis, value, _) => axis_from_gilrs(axis)
+                    .map(|axis| InputEvent::GamepadAxis { axis, value }),
+                _ => None,
+            };
+            events.extend(event);
+        }
+        events
+    }
+}
+
+fn button_from_gilrs(button: gilrs::Button) -> Option<GamepadButton> {
+    use gilrs::Button;
+
+    Some(match button {
+        Button::South => GamepadButton::South,
+        Button::East => GamepadButton::East,
+        Button::North => GamepadButton::North,
+        Button::West => GamepadButton::West,
+        Button::LeftTrigger => GamepadButton::LeftBumper,
+        Button::RightTrigger => GamepadButton::RightBumper,
+        Button::Select => GamepadButton::Select,
+        Button::Start => GamepadButton::Start,
+        Button::LeftThumb => GamepadButton::LeftThumb,
+        Button::RightThumb => GamepadButton::RightThumb,
+        Button::DPadUp => GamepadButton::DPadUp,
+        Button::DPadDown => GamepadButton::DPadDown,
+        Button::DPadLeft => GamepadButton::DPadLeft,
+        Button::DPadRight => GamepadButton::DPadRight,
+        // Triggers are handled as axes.
+        _ => return None,
+    })
+}
+
+fn axis_from_gilrs(axis: gilrs::Axis) -> Option<GamepadAxis> {
+    use gilrs::Axis;
+
+    Some(match axis {
+        Axis::LeftStickX => GamepadAxis::LeftStickX,
+        Axis::LeftStickY => GamepadAxis::LeftStickY,
+        Axis::RightStickX => GamepadAxis::RightStickX,
+        Axis::RightStickY => GamepadAxis::RightStickY,
+        Axis::LeftZ => GamepadAxis::LeftTrigger,
+        Axis::RightZ => GamepadAxis::RightTrigger,
+        _ => return None,
+    })
+}
@@ -0,0 +1,83 @@
+use crate::{
+    Gui, UiNode,
+    message::{Message, MessageHandler},
+};
+use rg3d::{
+    core::pool::Handle,
+    engine::RigidBodyHandle,
+    gui::{
+        message::{MessageDirection, TextMessage},
+        text::TextBuilder,
+        widget::WidgetBuilder,
+        Thickness,
+    },
+};
+
+pub struct Hud {
+    text: Handle<UiNode>,
+    player: RigidBodyHandle,
+    health: f32,
+    max_health: f32,
+    kills: u32,
+    wave: Option<u32>,
+    level_finished: bool,
+    dirty: bool,
+}
+
+impl Hud {
+    pub fn new(ui: &mut Gui, player: RigidBodyHandle, health: f32) -> Self {
+        let text = TextBuilder::new(WidgetBuilder::new().with_margin(Thickness::uniform(10.0)))
+            .build(&mut ui.build_ctx());
+
+        Self {
+            text,
+            player,
+            health,
+            max_health: health,
+            kills: 0,
+            wave: None,
+            level_finished: false,
+            dirty: true,
+        }
+    }
+
+    pub fn update(&mut self, ui: &mut Gui) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+
+        let mut text = format!("Health: {:.0}/{:.0}\nKills: {}",
+                               self.health, self.max_health, self.kills);
+        if let Some(wave) = self.wave {
+            text += &format!("\nWave: {}", wave);
+        }
+        if self.level_finished {
+            text += "\nLevel finished!";
+        }
+
+        ui.send_message(TextMessage::text(self.text, MessageDirection::ToWidget, text));
+    }
+}
+
+impl MessageHandler for Hud {
+    fn handle_message(&mut self, message: &Message) {
+        match message {
+            Message::DamageDealt { target, health, .. }
+            | Message::Healed { target, health, .. } if *target == self.player => {
+                self.health = *health;
+            }
+            Message::CharacterDied { body } if *body != self.player => {
+                self.kills += 1;
+            }
+            Message::WaveStarted { wave, .. } => {
+                self.wave = Some(*wave);
+            }
+            Message::LevelFinished => {
+                self.level_finished = true;
+            }
+            _ => return,
+        }
+        self.dirty = true;
+    }
+}
@@ -0,0 +1,228 @@
+use crate::{
+    keyboard_input::{InputEvent, parse_key, mouse_button_name, parse_mouse_button},
+    gamepad::{GamepadAxis, GamepadButton},
+    error::{GameError, GameResult},
+};
+use rg3d::utils::log::{Log, MessageKind};
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+// One event per line, prefixed by the fixed tick it has to be applied on:
+//
+//   seed 42
+//   120 key W 1
+//   120 mouse Left 1
+//   121 motion 1.5 -0.25
+//   122 wheel -1
+//   123 pad South 1
+//   124 axis LeftStickX 0.5
+//   125 release
+
+pub struct InputRecorder {
+    writer: BufWriter<File>,
+    path: PathBuf,
+}
+
+impl InputRecorder {
+    pub fn create(path: &Path, seed: u64) -> GameResult<Self> {
+        let error = |e: std::io::Error| recording_error(path, e.to_string());
+
+        let mut writer = BufWriter::new(File::create(path).map_err(error)?);
+        writeln!(writer, "seed {}", seed).map_err(error)?;
+
+        Ok(Self {
+            writer,
+            path: path.to_owned(),
+        })
+    }
+
+    pub fn record(&mut self, tick: u64, event: &InputEvent) {
+        let result = match event {
+            InputEvent::Key { code, pressed } => {
+                writeln!(self.writer, "{} key {:?} {}", tick, code, *pressed as u8)
+            }
+            InputEvent::MouseButton { button, pressed } => {
+                writeln!(self.writer, "{} mouse {} {}", tick, mouse_button_name(*button), *pressed as u8)
+            }
+            InputEvent::MouseMotion { delta } => {
+                writeln!(self.writer, "{} motion {} {}", tick, delta.0, delta.1)
+            }
+            InputEvent::Wheel { delta } => {
+                writeln!(self.writer, "{} wheel {}", tick, delta)
+            }
+            InputEvent::GamepadButton { button, pressed } => {
+                writeln!(self.writer, "{} pad {:?} {}", tick, button, *pressed as u8)
+            }
+            InputEvent::GamepadAxis { axis, value } => {
+                writeln!(self.writer, "{} axis {:?} {}", tick, axis, value)
+            }
+            InputEvent::ReleaseAll => {
+                writeln!(self.writer, "{} release", tick)
+            }
+        };
+
+        if let Err(e) = result {
+            self.report(e);
+        }
+    }
+
+    /// Writes the events recorded so far to the file, so that a crash loses
+    /// none of them. Called at the end of every tick.
+    pub fn flush(&mut self) {
+        if let Err(e) = self.writer.flush() {
+            self.report(e);
+        }
+    }
+
+    fn report(&self, error: std::io::Error) {
+        Log::writeln(MessageKind::Error,
+                     format!("Failed to record input to {}: {}", self.path.display(), error));
+    }
+}
+
+pub struct InputReplay {
+    pub seed: u64,
+    events: VecDeque<(u64, InputEvent)>,
+}
+
+impl InputReplay {
+    pub fn load(path: &Path) -> GameResult<Self> {
+        let source = fs::read_to_string(path)
+            .map_err(|e| recording_error(path, e.to_string()))?;
+
+        let mut lines = source.lines().enumerate();
+
+        let seed = lines
+            .next()
+            .and_then(|(_, line)| line.strip_prefix("seed "))
+            .and_then(|seed| seed.trim().parse().ok())
+            .ok_or_else(|| recording_error(path, "missing \"seed\" header".to_owned()))?;
+
+        let mut events = VecDeque::new();
+        for (number, line) in lines {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let event = parse_line(line)
+                .ok_or_else(|| recording_error(path, format!("line {}: invalid event \"{}\"", number + 1, line)))?;
+            events.push_back(event);
+        }
+
+        Ok(Self { seed, events })
+    }
+
+    /// Removes and returns the events recorded on `tick`.
+    pub fn events_for(&mut self, tick: u64) -> Vec<InputEvent> {
+        let mut events = Vec::new();
+        while let Some(&(event_tick, event)) = self.events.front() {
+            if event_tick > tick {
+                break;
+            }
+            events.push(event);
+            self.events.pop_front();
+        }
+        events
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+fn recording_error(path: &Path, reason: String) -> GameError {
+    GameError::Recording {
+        path: path.to_owned(),
+        reason,
+    }
+}
+
+fn parse_line(line: &str) -> Option<(u64, InputEvent)> {
+    let mut parts = line.split_whitespace();
+    let tick = parts.next()?.parse().ok()?;
+    let kind = parts.next()?;
+    let first = parts.next();
+    let second = parts.next();
+
+    let pressed = || match second? {
+        "1" => Some(true),
+        "0" => Some(false),
+        _ => None,
+    };
+
+    let event = match kind {
+        "key" => InputEvent::Key {
+            code: parse_key(first?)?,
+            pressed: pressed()?,
+        },
+        "mouse" => InputEvent::MouseButton {
+            button: parse_mouse_button(first?)?,
+            pressed: pressed()?,
+        },
+        "motion" => InputEvent::MouseMotion {
+            delta: (first?.parse().ok()?, second?.parse().ok()?),
+        },
+        "wheel" => InputEvent::Wheel {
+            delta: first?.parse().ok()?,
+        },
+        "pad" => InputEvent::GamepadButton {
+            button: GamepadButton::from_name(first?)?,
+            pressed: pressed()?,
+        },
+        "axis" => InputEvent::GamepadAxis {
+            axis: GamepadAxis::from_name(first?)?,
+            value: second?.parse().ok()?,
+        },
+        "release" => InputEvent::ReleaseAll,
+        _ => return None,
+    };
+
+    Some((tick, event))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gamepad::{GamepadAxis, GamepadButton};
+    use rg3d::event::{MouseButton, VirtualKeyCode};
+
+    #[test]
+    fn recorded_events_are_replayed_on_their_tick() {
+        let events = [
+            (0, InputEvent::Key { code: VirtualKeyCode::W, pressed: true }),
+            (0, InputEvent::MouseButton { button: MouseButton::Other(4), pressed: true }),
+            (1, InputEvent::MouseMotion { delta: (1.5, -0.25) }),
+            (1, InputEvent::Wheel { delta: -1.0 }),
+            (3, InputEvent::GamepadButton { button: GamepadButton::South, pressed: false }),
+            (3, InputEvent::GamepadAxis { axis: GamepadAxis::LeftStickX, value: -0.75 }),
+            (4, InputEvent::ReleaseAll),
+            (4, InputEvent::Key { code: VirtualKeyCode::W, pressed: false }),
+        ];
+
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("recording.txt");
+        let mut recorder = InputRecorder::create(&path, 42).unwrap();
+        for (tick, event) in events.iter() {
+            recorder.record(*tick, event);
+        }
+        recorder.flush();
+
+        // Read while the recorder is still open, as after a crash.
+        let mut replay = InputReplay::load(&path).unwrap();
+        drop(recorder);
+
+        assert_eq!(replay.seed, 42);
+        for tick in 0..5 {
+            let expected = events.iter()
+                .filter(|(event_tick, _)| *event_tick == tick)
+                .map(|(_, event)| *event)
+                .collect::<Vec<_>>();
+            assert_eq!(replay.events_for(tick), expected, "tick {}", tick);
+        }
+        assert!(replay.is_finished());
+    }
+}
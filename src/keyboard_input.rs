@@ -1,5 +1,11 @@
-use rg3d::event::{WindowEvent, Event};
-use std::collections::HashMap;
+use crate::gamepad::{GamepadAxis, GamepadButton};
+use rg3d::event::{WindowEvent, Event, DeviceEvent, ElementState, MouseButton, MouseScrollDelta};
+use serde::{Deserialize, de::{IntoDeserializer, value::Error as ValueError}};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fmt,
+};
 use rg3d::event::VirtualKeyCode;
 
 #[derive(Debug, Hash, Ord, PartialOrd, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
@@ -11,6 +17,224 @@ pub enum Action {
     Jump,
     Attack,
 }
- 
-pub type KeyMap = HashMap<VirtualKeyCode, Action>;
 
+impl Action {
+    pub const ALL: &'static [Action] = &[
+        Action::Forward,
+        Action::Backward,
+        Action::Left,
+        Action::Right,
+        Action::Jump,
+        Action::Attack,
+    ];
+}
+
+/// Something that can be pressed.
+#[derive(Debug, Hash, PartialEq, Eq, Clone, Copy)]
+pub enum Input {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+    Gamepad(GamepadButton),
+    /// A wheel notch is pressed and released within a single tick.
+    WheelUp,
+    WheelDown,
+}
+
+#[derive(Debug, Default, Hash, PartialEq, Eq, Clone, Copy)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+}
+
+impl Modifiers {
+    /// True if every modifier of `self` is held in `held`.
+    pub fn held_in(&self, held: Modifiers) -> bool {
+        (!self.ctrl || held.ctrl) && (!self.shift || held.shift) && (!self.alt || held.alt)
+    }
+
+    pub fn count(&self) -> usize {
+        self.ctrl as usize + self.shift as usize + self.alt as usize
+    }
+}
+
+/// An input along with the modifiers that have to be held with it, written as
+/// e.g. `W`, `Ctrl+Shift+S`, `MouseLeft`, `MouseOther4`, `WheelUp` or `PadSouth`.
+#[derive(Debug, Hash, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Binding {
+    pub modifiers: Modifiers,
+    pub input: Input,
+}
+
+impl TryFrom<String> for Binding {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        let mut parts = source.split('+').map(str::trim).collect::<Vec<_>>();
+        let input = parts.pop().unwrap_or_default();
+
+        let mut modifiers = Modifiers::default();
+        for modifier in parts {
+            match modifier {
+                "Ctrl" => modifiers.ctrl = true,
+                "Shift" => modifiers.shift = true,
+                "Alt" => modifiers.alt = true,
+                _ => return Err(format!("unknown modifier \"{}\" in \"{}\"", modifier, source)),
+            }
+        }
+
+        let input = match input {
+            "WheelUp" => Input::WheelUp,
+            "WheelDown" => Input::WheelDown,
+            _ => input.strip_prefix("Mouse")
+                .and_then(parse_mouse_button)
+                .map(Input::Mouse)
+                .or_else(|| input.strip_prefix("Pad").and_then(GamepadButton::from_name).map(Input::Gamepad))
+                .or_else(|| parse_key(input).map(Input::Key))
+                .ok_or_else(|| format!("unknown input \"{}\" in \"{}\"", input, source))?,
+        };
+
+        Ok(Self { modifiers, input })
+    }
+}
+
+impl From<Binding> for String {
+    fn from(binding: Binding) -> Self {
+        binding.to_string()
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.modifiers.ctrl { write!(f, "Ctrl+")?; }
+        if self.modifiers.shift { write!(f, "Shift+")?; }
+        if self.modifiers.alt { write!(f, "Alt+")?; }
+
+        match self.input {
+            Input::Key(code) => write!(f, "{:?}", code),
+            Input::Mouse(button) => write!(f, "Mouse{}", mouse_button_name(button)),
+            Input::Gamepad(button) => write!(f, "Pad{:?}", button),
+            Input::WheelUp => write!(f, "WheelUp"),
+            Input::WheelDown => write!(f, "WheelDown"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct GamepadSettings {
+    /// Stick deflection below which the stick counts as centered.
+    pub deadzone: f32,
+    /// Stick deflection above which the stick counts as fully pushed.
+    pub outer_deadzone: f32,
+    /// Trigger travel past which the trigger counts as pressed.
+    pub trigger_threshold: f32,
+    /// Camera turn speed with the right stick fully pushed, in degrees per second.
+    pub look_speed: f32,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct KeyMap {
+    /// Longest time in seconds between two presses of an action that count as a double tap.
+    pub double_tap_interval: f32,
+    pub gamepad: GamepadSettings,
+    pub bindings: HashMap<Action, Vec<Binding>>,
+}
+
+#[cfg(test)]
+impl KeyMap {
+    /// A keymap for tests with the given bindings, e.g. `(Action::Jump, "Space")`.
+    pub fn with_bindings(bindings: &[(Action, &str)]) -> Self {
+        let mut keymap = KeyMap {
+            double_tap_interval: 0.3,
+            gamepad: GamepadSettings {
+                deadzone: 0.2,
+                outer_deadzone: 0.9,
+                trigger_threshold: 0.5,
+                look_speed: 100.0,
+            },
+            bindings: HashMap::new(),
+        };
+        for (action, source) in bindings {
+            keymap.bindings
+                .entry(*action)
+                .or_default()
+                .push(Binding::try_from(source.to_string()).unwrap());
+        }
+        keymap
+    }
+}
+
+#[cfg(test)]
+pub fn key(code: VirtualKeyCode, pressed: bool) -> InputEvent {
+    InputEvent::Key { code, pressed }
+}
+
+pub fn parse_key(name: &str) -> Option<VirtualKeyCode> {
+    VirtualKeyCode::deserialize(name.into_deserializer())
+        .map_err(|_: ValueError| ())
+        .ok()
+}
+
+pub fn mouse_button_name(button: MouseButton) -> String {
+    match button {
+        MouseButton::Left => "Left".to_owned(),
+        MouseButton::Right => "Right".to_owned(),
+        MouseButton::Middle => "Middle".to_owned(),
+        MouseButton::Other(id) => format!("Other{}", id),
+    }
+}
+
+pub fn parse_mouse_button(name: &str) -> Option<MouseButton> {
+    match name {
+        "Left" => Some(MouseButton::Left),
+        "Right" => Some(MouseButton::Right),
+        "Middle" => Some(MouseButton::Middle),
+        _ => name.strip_prefix("Other")?.parse().ok().map(MouseButton::Other),
+    }
+}
+
+/// The subset of window events that gameplay reacts to. Unlike `Event`,
+/// these can be recorded and replayed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputEvent {
+    Key { code: VirtualKeyCode, pressed: bool },
+    MouseButton { button: MouseButton, pressed: bool },
+    MouseMotion { delta: (f64, f64) },
+    /// Lines scrolled, positive away from the user.
+    Wheel { delta: f32 },
+    GamepadButton { button: GamepadButton, pressed: bool },
+    GamepadAxis { axis: GamepadAxis, value: f32 },
+    /// Everything held is released, e.g. when a menu takes the input over.
+    ReleaseAll,
+}
+
+impl InputEvent {
+    pub fn from_event(event: &Event<()>) -> Option<Self> {
+        match event {
+            Event::WindowEvent { event: WindowEvent::KeyboardInput { input, .. }, .. } => {
+                input.virtual_keycode.map(|code| InputEvent::Key {
+                    code,
+                    pressed: input.state == ElementState::Pressed,
+                })
+            }
+            Event::WindowEvent { event: WindowEvent::MouseInput { state, button, .. }, .. } => {
+                Some(InputEvent::MouseButton {
+                    button: *button,
+                    pressed: *state == ElementState::Pressed,
+                })
+            }
+            Event::WindowEvent { event: WindowEvent::MouseWheel { delta, .. }, .. } => {
+                let delta = match delta {
+                    MouseScrollDelta::LineDelta(_, lines) => *lines,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32,
+                };
+                Some(InputEvent::Wheel { delta }).filter(|_| delta != 0.0)
+            }
+            Event::DeviceEvent { event: DeviceEvent::MouseMotion { delta }, .. } => {
+                Some(InputEvent::MouseMotion { delta: *delta })
+            }
+            _ => None,
+        }
+    }
+}
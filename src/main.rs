@@ -8,90 +8,146 @@ extern crate serde_derive;
 
 mod message;
 mod keyboard_input;
+mod gamepad;
+mod controls;
+mod rebinding;
+mod controls_menu;
 mod character_body;
+mod animation_rule;
 mod character_animation;
 mod character;
 mod attached_camera;
+mod mouse_look;
 mod rotating_camera;
 mod weapon;
 mod player;
+mod behavior_tree;
+mod navmesh;
+mod perception;
+mod steering;
 mod bot_ai;
 mod bot;
 mod movement_controller;
 mod settings;
+mod assets;
 mod resource_helper;
+mod error;
+mod simulation;
+mod cli;
+mod input_recording;
+mod hud;
+mod settings_watcher;
+mod waves;
+mod validation;
 
 use rg3d::renderer::QualitySettings;
 use rg3d::{
     dpi::LogicalSize,
     core::{
         futures::executor::block_on,
-        pool::{Pool, Handle},
-        algebra::Vector3,
+        pool::Handle,
     },
-    engine::Engine,
+    engine::{Engine, resource_manager::ResourceManager},
     scene::Scene,
     event::{DeviceEvent, Event, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     gui::{
-        node::StubNode,
+        node::{StubNode, UINode},
         UserInterface,
     },
+    utils::log::{Log, MessageKind},
 };
-use once_cell::sync::Lazy;
+use once_cell::sync::OnceCell;
 use crate::{
-    settings::Settings,
-    message::Message,
-    player::Player,
-    bot::Bot,
+    settings::{Settings, SettingsSources},
+    message::MessageHandler,
+    hud::Hud,
+    controls_menu::ControlsMenu,
+    settings_watcher::SettingsWatcher,
+    validation::{validate, validate_models},
+    simulation::{Simulation, Headless},
+    cli::Options,
+    keyboard_input::InputEvent,
+    gamepad::{GamepadBackend, GilrsBackend},
+    input_recording::{InputRecorder, InputReplay},
+    error::{GameError, GameResult},
 };
 use std::{
     fs::File,
     io::Write,
-    sync::{
-        mpsc::{self, Receiver, Sender},
-        RwLock,
-    },
-    future::Future,
-    time::{self, Instant},
+    sync::{RwLock, RwLockReadGuard},
+    time,
 };
 use rand::Rng;
 
 const FIXED_FPS: f32 = 60.0;
 const FIXED_TIMESTEP: f32 = 1.0 / FIXED_FPS;
 
-static SETTINGS: Lazy<RwLock<Settings>> = Lazy::new(|| {
-    RwLock::new(Settings::new().unwrap())
-});
+static SETTINGS: OnceCell<RwLock<Settings>> = OnceCell::new();
+
+/// Panics if called before `main` has loaded the settings, which is a bug rather than
+/// a failure to report. The lock is only poisoned if a panic already happened while
+/// the settings were being replaced.
+pub fn read_settings() -> RwLockReadGuard<'static, Settings> {
+    SETTINGS
+        .get()
+        .expect("settings are loaded before the game starts")
+        .read()
+        .unwrap()
+}
+
+/// Swaps the global settings, e.g. after they were edited while the game runs.
+/// Panics under the same conditions as `read_settings`.
+pub fn replace_settings(settings: Settings) {
+    *SETTINGS
+        .get()
+        .expect("settings are loaded before the game starts")
+        .write()
+        .unwrap() = settings;
+}
 
 // Define type aliases for engine structs.
 pub type MyEventLoop = EventLoop<()>;
 pub type GameEngine = Engine<(), StubNode>;
 pub type Gui = UserInterface<(), StubNode>;
+pub type UiNode = UINode<(), StubNode>;
 
 pub struct Game {
     engine: GameEngine,
     scene: Handle<Scene>,
     last_tick_time: time::Instant,
+    /// Wall clock, only used to decide how many fixed ticks to run.
+    clock: time::Instant,
     running: bool,
     time: GameTime,
-    events_receiver: Receiver<Message>,
-    events_sender: Sender<Message>,
-    player: Player,
-    bots: Pool<Bot>,
+    simulation: Simulation,
+    hud: Hud,
+    controls_menu: ControlsMenu,
+    settings_watcher: SettingsWatcher,
+    gamepad: Box<dyn GamepadBackend>,
 }
 
+/// Simulation time, advanced only by fixed ticks.
 #[derive(Copy, Clone)]
 pub struct GameTime {
-    clock: time::Instant,
     elapsed: f64,
     delta: f32,
 }
 
-impl Game {
-    pub async fn new(event_loop: &MyEventLoop, title: &'static str) -> Self {
-        let (sender, receiver) = mpsc::channel();
+impl GameTime {
+    pub fn new() -> Self {
+        Self {
+            elapsed: 0.0,
+            delta: FIXED_TIMESTEP,
+        }
+    }
+}
 
+impl Game {
+    pub async fn new(event_loop: &MyEventLoop,
+                     title: &'static str,
+                     seed: u64,
+                     sources: SettingsSources) -> GameResult<Self> {
         let inner_size = get_inner_size(event_loop);
 
         let window_builder = rg3d::window::WindowBuilder::new()
@@ -99,40 +155,40 @@ impl Game {
             .with_inner_size(inner_size)
             .with_resizable(true);
 
-        let mut engine = GameEngine::new(window_builder, event_loop, false).unwrap();
-        engine.renderer.set_quality_settings(&QualitySettings::high()).unwrap();
+        let mut engine = GameEngine::new(window_builder, event_loop, false)
+            .map_err(|error| GameError::Engine(format!("{:?}", error)))?;
+        engine.renderer.set_quality_settings(&QualitySettings::high())
+            .map_err(|error| GameError::Engine(format!("{:?}", error)))?;
 
-        let time = GameTime {
-            clock: Instant::now(),
-            elapsed: 0.0,
-            delta: FIXED_TIMESTEP,
-        };
+        let time = GameTime::new();
 
         let window = engine.get_window();
         window.set_cursor_visible(false);
-        window.set_cursor_grab(true).unwrap();
+        window.set_cursor_grab(true)
+            .map_err(|error| GameError::Engine(format!("{:?}", error)))?;
 
-        let resource_manager = &engine.resource_manager;
         let mut scene = Scene::new();
-
-        request_scene!(&engine.resource_manager, main.model)
-            .instantiate_geometry(&mut scene);
-
-        let player = Player::new(&mut scene, resource_manager, sender.clone()).await;
+        let simulation = Simulation::load(&mut scene, &engine.resource_manager, &read_settings(), seed).await?;
 
         let scene = engine.scenes.add(scene);
 
-        Self {
+        let player = &simulation.player;
+        let hud = Hud::new(&mut engine.user_interface, player.body.body, player.max_health);
+        let controls_menu = ControlsMenu::new(&mut engine.user_interface, sources.clone());
+
+        Ok(Self {
             running: true,
             engine,
             scene,
             last_tick_time: time::Instant::now(),
+            clock: time::Instant::now(),
             time,
-            events_sender: sender,
-            events_receiver: receiver,
-            player,
-            bots: Default::default(),
-        }
+            simulation,
+            hud,
+            controls_menu,
+            settings_watcher: SettingsWatcher::new(sources),
+            gamepad: GilrsBackend::create(),
+        })
     }
 
     pub fn run(mut game: Self, event_loop: MyEventLoop) {
@@ -141,7 +197,11 @@ impl Game {
 
             match event {
                 Event::MainEventsCleared => {
-                    let mut dt = game.time.clock.elapsed().as_secs_f64() - game.time.elapsed;
+                    for event in game.gamepad.poll() {
+                        game.process_input(&event);
+                    }
+
+                    let mut dt = game.clock.elapsed().as_secs_f64() - game.time.elapsed;
                     while dt >= FIXED_TIMESTEP as f64 {
                         dt -= FIXED_TIMESTEP as f64;
                         game.time.elapsed += FIXED_TIMESTEP as f64;
@@ -190,37 +250,55 @@ impl Game {
     pub fn update(&mut self, time: GameTime) {
         let scene = &mut self.engine.scenes[self.scene];
 
-        self.player.update(scene, time);
-
-        for bot in self.bots.iter_mut() {
-            bot.update(scene, time);
+        if self.settings_watcher.poll() {
+            // Recordings don't store settings, so a change in the middle of one
+            // would make its replay diverge from what was recorded.
+            if self.simulation.recorder.is_some() || self.simulation.replay.is_some() {
+                Log::writeln(MessageKind::Warning,
+                             "Settings changed, not reloaded while recording or replaying".to_owned());
+            } else {
+                match self.settings_watcher.reload() {
+                    Ok(settings) => {
+                        self.simulation.apply_settings(scene, &settings);
+                        replace_settings(settings);
+                        Log::writeln(MessageKind::Information, "Settings reloaded".to_owned());
+                    }
+                    Err(error) => {
+                        Log::writeln(MessageKind::Error,
+                                     format!("Settings not reloaded, keeping the previous ones: {}", error));
+                    }
+                }
+            }
         }
-    }
 
-    fn process_input_event(&mut self, event: &Event<()>) {
-        self.player.process_input_event(event);
-    }
-
-    async fn create_bot(&mut self, position: Vector3<f32>) {
-        let scene = &mut self.engine.scenes[self.scene];
+        let messages = self.simulation.update(scene, &read_settings(), time);
 
-        let bot = Bot::new(scene,
-                           &self.engine.resource_manager,
-                           self.events_sender.clone(), 
-                           position)
-            .await;
+        for message in messages.iter() {
+            self.hud.handle_message(message);
+        }
 
-        self.bots.spawn(bot);
+        self.hud.update(&mut self.engine.user_interface);
+        self.controls_menu.update(&mut self.engine.user_interface);
     }
 
-    async fn spawn_bots(&mut self) {
-        let get_random_cord = || rand::thread_rng().gen_range(-5.0..5.0);
-
-        for _ in 0..2 {
-            let pos = Vector3::new(get_random_cord(), 2.0, get_random_cord());
+    fn process_input_event(&mut self, event: &Event<()>) {
+        if let Some(event) = InputEvent::from_event(event) {
+            self.process_input(&event);
+        }
+    }
 
-            self.create_bot(pos).await;
+    fn process_input(&mut self, event: &InputEvent) {
+        let was_open = self.controls_menu.is_open();
+        if self.controls_menu.process_input_event(event, &read_settings().keymap) {
+            if !was_open {
+                // Keys held when the menu opens are released while it is open. Going through
+                // the simulation records the release and keeps it away from a replay.
+                self.simulation.process_input_event(&InputEvent::ReleaseAll);
+            }
+            return;
         }
+
+        self.simulation.process_input_event(event);
     }
 }
 
@@ -232,9 +310,80 @@ fn get_inner_size(event_loop: &MyEventLoop) -> LogicalSize<f32> {
     monitor_dimensions.to_logical::<f32>(primary_monitor.scale_factor())
 }
 
-fn main() {
+fn start() -> GameResult<()> {
+    let options = Options::parse()?;
+
+    let sources = SettingsSources {
+        overrides: options.overrides,
+        ..Default::default()
+    };
+
+    if options.print_settings {
+        print!("{}", sources.effective()?);
+        return Ok(());
+    }
+
+    if !sources.directory.is_dir() {
+        Log::writeln(MessageKind::Warning,
+                     format!("Settings directory {} not found, using the built-in settings",
+                             sources.directory.display()));
+    }
+
+    let settings = Settings::load(&sources)?;
+
+    if options.check_settings {
+        return check_settings(&settings, &sources);
+    }
+
+    let problems = validate(&settings, &sources);
+    if !problems.is_empty() {
+        return Err(GameError::Validation(problems));
+    }
+
+    let replay = options.replay.as_deref().map(InputReplay::load).transpose()?;
+    let seed = replay.as_ref().map(|replay| replay.seed)
+        .or(options.seed)
+        .or(settings.seed)
+        .unwrap_or_else(|| rand::thread_rng().gen());
+    let recorder = options.record.as_deref()
+        .map(|path| InputRecorder::create(path, seed))
+        .transpose()?;
+
+    if let Some(ticks) = options.headless {
+        let mut headless = block_on(Headless::new(settings, seed))?;
+        headless.simulation.recorder = recorder;
+        headless.simulation.replay = replay;
+        headless.run(ticks);
+        return Ok(());
+    }
+
+    let _ = SETTINGS.set(RwLock::new(settings));
+
     let event_loop = MyEventLoop::new();
-    let mut game = block_on(Game::new(&event_loop, "Jam"));
-    block_on(game.spawn_bots());
+    let mut game = block_on(Game::new(&event_loop, "Jam", seed, sources))?;
+    game.simulation.recorder = recorder;
+    game.simulation.replay = replay;
     Game::run(game, event_loop);
+
+    Ok(())
+}
+
+/// Runs every check, including those that load the models.
+fn check_settings(settings: &Settings, sources: &SettingsSources) -> GameResult<()> {
+    let mut problems = validate(settings, sources);
+    problems.extend(block_on(validate_models(&ResourceManager::new(), settings, sources)));
+
+    if !problems.is_empty() {
+        return Err(GameError::Validation(problems));
+    }
+
+    println!("Settings are valid");
+    Ok(())
+}
+
+fn main() {
+    if let Err(error) = start() {
+        eprintln!("error: {}", error);
+        std::process::exit(1);
+    }
 }
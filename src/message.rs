@@ -1,3 +1,48 @@
-#[derive(Debug)]
+use rg3d::{
+    core::algebra::Vector3,
+    engine::RigidBodyHandle,
+};
+
+#[derive(Debug, Clone)]
 pub enum Message {
+    DamageDealt {
+        target: RigidBodyHandle,
+        amount: f32,
+        health: f32,
+    },
+    Healed {
+        target: RigidBodyHandle,
+        amount: f32,
+        health: f32,
+    },
+    CharacterDied {
+        body: RigidBodyHandle,
+    },
+    BotSpawned {
+        body: RigidBodyHandle,
+    },
+    AttackStarted {
+        attacker: RigidBodyHandle,
+        position: Vector3<f32>,
+    },
+    Footstep {
+        source: RigidBodyHandle,
+        position: Vector3<f32>,
+    },
+    /// Waves are numbered from 1.
+    WaveStarted {
+        wave: u32,
+        bots: u32,
+    },
+    WaveCleared {
+        wave: u32,
+    },
+    LevelFinished,
+}
+
+/// Anything that reacts to game events. Messages sent during a fixed tick are
+/// dispatched once, at the end of `Simulation::update`: to the characters and the
+/// simulation itself, then to the HUD, which `Game::update` hands them to.
+pub trait MessageHandler {
+    fn handle_message(&mut self, message: &Message);
 }
@@ -0,0 +1,113 @@
+use crate::settings::CameraSettings;
+use rg3d::core::algebra::Vector2;
+
+/// Turns mouse motion into camera turns in degrees, x to the right and y down,
+/// applying sensitivity, inversion, acceleration and smoothing.
+pub struct MouseLook {
+    settings: CameraSettings,
+    /// Mouse counts received since the last tick.
+    pending: Vector2<f32>,
+    /// Turn applied during the last tick, in degrees.
+    smoothed: Vector2<f32>,
+}
+
+impl MouseLook {
+    pub fn new(settings: &CameraSettings) -> Self {
+        Self {
+            settings: settings.clone(),
+            pending: Vector2::zeros(),
+            smoothed: Vector2::zeros(),
+        }
+    }
+
+    pub fn apply_settings(&mut self, settings: &CameraSettings) {
+        self.settings = settings.clone();
+    }
+
+    pub fn process_motion(&mut self, delta: (f64, f64)) {
+        self.pending += Vector2::new(delta.0 as f32, delta.1 as f32);
+    }
+
+    /// Returns the turn for a tick of `delta` seconds.
+    pub fn update(&mut self, delta: f32) -> Vector2<f32> {
+        let settings = &self.settings;
+        let motion = std::mem::replace(&mut self.pending, Vector2::zeros());
+
+        let speed = motion.norm() / delta.max(f32::EPSILON);
+        let sensitivity = settings.sensitivity * (1.0 + settings.acceleration * speed / 1000.0);
+        let mut turn = motion * sensitivity;
+        if settings.invert_y {
+            turn.y = -turn.y;
+        }
+
+        if settings.smoothing > 0.0 {
+            // Exponential smoothing keeps the total turn, only spreading it over time.
+            let blend = 1.0 - (-delta / settings.smoothing).exp();
+            self.smoothed += (turn - self.smoothed) * blend;
+        } else {
+            self.smoothed = turn;
+        }
+        self.smoothed
+    }
+
+    /// Forgets motion that was not turned yet.
+    pub fn clear(&mut self) {
+        self.pending = Vector2::zeros();
+        self.smoothed = Vector2::zeros();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> CameraSettings {
+        CameraSettings {
+            offset: (0.0, 0.0, 0.0),
+            hinge_offset: (0.0, 0.0, 0.0),
+            sensitivity: 0.5,
+            invert_y: false,
+            smoothing: 0.0,
+            acceleration: 0.0,
+            pitch_limits: (-90.0, 90.0),
+        }
+    }
+
+    #[test]
+    fn motion_is_scaled_by_sensitivity_in_both_directions() {
+        let mut look = MouseLook::new(&settings());
+        look.process_motion((10.0, 4.0));
+        look.process_motion((2.0, 0.0));
+
+        assert_eq!(look.update(0.1), Vector2::new(6.0, 2.0));
+        assert_eq!(look.update(0.1), Vector2::zeros());
+
+        look.apply_settings(&CameraSettings { invert_y: true, ..settings() });
+        look.process_motion((0.0, 4.0));
+        assert_eq!(look.update(0.1), Vector2::new(0.0, -2.0));
+    }
+
+    #[test]
+    fn acceleration_turns_fast_motion_further() {
+        let mut look = MouseLook::new(&CameraSettings { acceleration: 1.0, ..settings() });
+
+        look.process_motion((1.0, 0.0));
+        let slow = look.update(0.1).x;
+        look.process_motion((100.0, 0.0));
+        let fast = look.update(0.1).x;
+
+        assert!(fast / 100.0 > slow * 1.5);
+    }
+
+    #[test]
+    fn smoothing_spreads_the_turn_over_ticks() {
+        let mut look = MouseLook::new(&CameraSettings { smoothing: 0.05, ..settings() });
+
+        look.process_motion((20.0, 0.0));
+        let first = look.update(0.01).x;
+        assert!(first > 0.0 && first < 10.0);
+
+        let total = first + (0..200).map(|_| look.update(0.01).x).sum::<f32>();
+        assert!((total - 10.0).abs() < 1e-2, "turned {}", total);
+    }
+}
@@ -1,53 +1,35 @@
 use crate::{
-    SETTINGS,
     settings::CharacterSpeedSettings,
-    keyboard_input::{KeyMap, Action},
+    keyboard_input::Action,
+    controls::Controls,
     character_body::CharacterBody,
     character_animation::CharacterAnimationInput,
 };
 use rg3d::{
     scene::{Scene, node::Node},
     core::{algebra::Vector3, pool::Handle},
-    event::{Event, WindowEvent, ElementState},
 };
-use std::collections::HashMap;
 
 pub struct MovementControlelr {
-    pub keymap: KeyMap,
-    pub actions: HashMap<Action, bool>,
     speed: CharacterSpeedSettings,
 }
 
 impl MovementControlelr {
     pub fn new(speed: CharacterSpeedSettings) -> Self {
-        let keymap = SETTINGS.read().unwrap().keymap.clone();
-
         Self { 
-            keymap,
-            actions: Default::default(),
             speed,
         }
     }
 
-    pub fn process_input_event(&mut self, event: &Event<()>) {
-        if let Event::WindowEvent { event, .. } = event {
-            if let WindowEvent::KeyboardInput { input, .. } = event {
-                let state = input.state == ElementState::Pressed;
-
-                if let Some(code) = &input.virtual_keycode {
-                    if self.keymap.contains_key(code) {
-                        let action = self.keymap[code];
-                        *self.actions.entry(action).or_insert(false) = state;
-                    }
-                }
-            }
-        }
+    pub fn apply_settings(&mut self, speed: CharacterSpeedSettings) {
+        self.speed = speed;
     }
 
     pub fn update(&mut self,
                              scene: &mut Scene, 
                              camera_pivot: Handle<Node>,
-                             body: &mut CharacterBody) -> CharacterAnimationInput {
+                             body: &mut CharacterBody,
+                             controls: &Controls) -> CharacterAnimationInput {
         let mut animation_input = CharacterAnimationInput::default();
 
         let pivot = &scene.graph[camera_pivot];
@@ -61,19 +43,14 @@ impl MovementControlelr {
             .get_mut(&body.body)
             .unwrap();
 
-        let mut velocity = Vector3::default();
+        // Keys give full speed, a stick as much as it is pushed.
+        let movement = controls.movement();
+        let mut velocity = look * movement.y - side * movement.x;
 
-        if self.action_state(Action::Left) { velocity += side; }
-        if self.action_state(Action::Right) { velocity -= side; }
-        if self.action_state(Action::Forward) { velocity += look; }
-        if self.action_state(Action::Backward) { velocity -= look; }
-        if self.action_state(Action::Jump) {
-            if has_ground_contact {
-                velocity += Vector3::new(0.0, 1.0, 0.0);
+        if controls.pressed(Action::Jump) && has_ground_contact {
+            velocity += Vector3::new(0.0, 1.0, 0.0);
 
-                animation_input.just_started_jumping = !animation_input.jumping;
-            }
-            *self.actions.get_mut(&Action::Jump).unwrap() = false;
+            animation_input.just_started_jumping = !animation_input.jumping;
         }
 
         body.set_angvel(Default::default(), true);
@@ -84,9 +61,9 @@ impl MovementControlelr {
             let speed = &self.speed;
             body.set_linvel(
                 Vector3::new(
-                    normalized_velocity.x * speed.run,
+                    normalized_velocity.x * speed.run * movement.norm(),
                     body.linvel().y + normalized_velocity.y * speed.jump,
-                    normalized_velocity.z * speed.run,
+                    normalized_velocity.z * speed.run * movement.norm(),
                     ),
                     true,
                     );
@@ -105,8 +82,4 @@ impl MovementControlelr {
 
         animation_input
     }
-
-    pub fn action_state(&mut self, action: Action) -> bool {
-        *self.actions.entry(action).or_insert(false)
-    }
 }
@@ -0,0 +1,413 @@
+use crate::settings::NavMeshSettings;
+use rg3d::{
+    scene::physics::{Physics, RayCastOptions},
+    core::{
+        algebra::Vector3,
+        math::ray::Ray,
+    },
+};
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+};
+
+type Cell = (usize, usize);
+
+/// Walkable grid over the XZ plane, sampled from the static level geometry.
+pub struct NavMesh {
+    origin: (f32, f32),
+    cell_size: f32,
+    width: usize,
+    depth: usize,
+    /// Ground height of every cell, `None` where there is nothing to stand on.
+    heights: Vec<Option<f32>>,
+    max_step: f32,
+}
+
+impl NavMesh {
+    /// Casts a ray down through every cell of the configured bounds.
+    /// Must be called before any dynamic bodies are added to the scene,
+    /// otherwise they are baked in as obstacles.
+    pub fn build(physics: &Physics, settings: &NavMeshSettings) -> Self {
+        let cell_size = settings.cell_size;
+        let width = ((settings.max.0 - settings.min.0) / cell_size).ceil().max(1.0) as usize;
+        let depth = ((settings.max.1 - settings.min.1) / cell_size).ceil().max(1.0) as usize;
+        let (floor, ceiling) = settings.height_range;
+
+        let mut heights = Vec::with_capacity(width * depth);
+        let mut intersections = Vec::new();
+
+        for z in 0..depth {
+            for x in 0..width {
+                let origin = Vector3::new(settings.min.0 + (x as f32 + 0.5) * cell_size,
+                                          ceiling,
+                                          settings.min.1 + (z as f32 + 0.5) * cell_size);
+
+                intersections.clear();
+                physics.cast_ray(
+                    RayCastOptions {
+                        ray: Ray::new(origin, Vector3::new(0.0, floor - ceiling, 0.0)),
+                        max_len: ceiling - floor,
+                        groups: Default::default(),
+                        sort_results: true,
+                    },
+                    &mut intersections,
+                );
+
+                heights.push(intersections.first().map(|intersection| intersection.position.y));
+            }
+        }
+
+        Self::from_heights(settings.min, cell_size, width, depth, heights, settings.max_step)
+            .with_clearance(settings.agent_radius)
+    }
+
+    /// `heights` is laid out row by row along Z, `width` cells per row.
+    pub fn from_heights(origin: (f32, f32),
+                        cell_size: f32,
+                        width: usize,
+                        depth: usize,
+                        heights: Vec<Option<f32>>,
+                        max_step: f32) -> Self {
+        assert_eq!(heights.len(), width * depth);
+
+        Self {
+            origin,
+            cell_size,
+            width,
+            depth,
+            heights,
+            max_step,
+        }
+    }
+
+    /// Removes the cells that have a wall or a drop within `radius`,
+    /// so that an agent of that radius following a path doesn't scrape against them.
+    pub fn with_clearance(mut self, radius: f32) -> Self {
+        let reach = (radius / self.cell_size).ceil() as isize;
+        if reach <= 0 {
+            return self;
+        }
+
+        let heights = (0..self.depth)
+            .flat_map(|z| (0..self.width).map(move |x| (x, z)))
+            .map(|cell| {
+                let height = self.height(cell)?;
+
+                for dz in -reach..=reach {
+                    for dx in -reach..=reach {
+                        let neighbour = match self.offset(cell, dx, dz) {
+                            Some(neighbour) => neighbour,
+                            None => continue,
+                        };
+                        let allowed = self.max_step * dx.abs().max(dz.abs()) as f32;
+
+                        match self.height(neighbour) {
+                            Some(other) if (other - height).abs() <= allowed => (),
+                            _ => return None,
+                        }
+                    }
+                }
+
+                Some(height)
+            })
+            .collect();
+
+        self.heights = heights;
+        self
+    }
+
+    pub fn walkable_count(&self) -> usize {
+        self.heights.iter().filter(|height| height.is_some()).count()
+    }
+
+    /// Returns the waypoints leading from `from` to `to`, excluding the start and
+    /// ending at `to`, or `None` if there is no walkable route between them.
+    pub fn find_path(&self, from: Vector3<f32>, to: Vector3<f32>) -> Option<Vec<Vector3<f32>>> {
+        let start = self.nearest_walkable(self.cell_at(from))?;
+        let goal_cell = self.cell_at(to);
+        let goal = self.nearest_walkable(goal_cell)?;
+
+        let cells = self.smooth(&self.search(start, goal)?);
+
+        let mut path = cells.iter()
+            .skip(if cells.len() > 1 { 1 } else { 0 })
+            .map(|&cell| self.cell_center(cell))
+            .collect::<Vec<_>>();
+
+        // Reach the exact destination when it lies on the mesh.
+        if goal == goal_cell {
+            if let Some(last) = path.last_mut() {
+                last.x = to.x;
+                last.z = to.z;
+            }
+        }
+
+        Some(path)
+    }
+
+    fn cell_at(&self, position: Vector3<f32>) -> Cell {
+        let x = ((position.x - self.origin.0) / self.cell_size).floor();
+        let z = ((position.z - self.origin.1) / self.cell_size).floor();
+
+        (x.max(0.0).min(self.width as f32 - 1.0) as usize,
+         z.max(0.0).min(self.depth as f32 - 1.0) as usize)
+    }
+
+    fn cell_center(&self, cell: Cell) -> Vector3<f32> {
+        Vector3::new(self.origin.0 + (cell.0 as f32 + 0.5) * self.cell_size,
+                     self.height(cell).unwrap_or_default(),
+                     self.origin.1 + (cell.1 as f32 + 0.5) * self.cell_size)
+    }
+
+    fn height(&self, cell: Cell) -> Option<f32> {
+        self.heights[cell.1 * self.width + cell.0]
+    }
+
+    fn offset(&self, cell: Cell, dx: isize, dz: isize) -> Option<Cell> {
+        let x = cell.0 as isize + dx;
+        let z = cell.1 as isize + dz;
+
+        if x < 0 || z < 0 || x >= self.width as isize || z >= self.depth as isize {
+            None
+        } else {
+            Some((x as usize, z as usize))
+        }
+    }
+
+    fn nearest_walkable(&self, cell: Cell) -> Option<Cell> {
+        let max_ring = self.width.max(self.depth) as isize;
+
+        for ring in 0..max_ring {
+            for dz in -ring..=ring {
+                for dx in -ring..=ring {
+                    if dx.abs() != ring && dz.abs() != ring {
+                        continue;
+                    }
+
+                    if let Some(neighbour) = self.offset(cell, dx, dz) {
+                        if self.height(neighbour).is_some() {
+                            return Some(neighbour);
+                        }
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Whether an agent can move between two adjacent cells. Diagonal moves
+    /// also need both of the orthogonal moves, so that corners are not cut.
+    fn can_step(&self, from: Cell, to: Cell) -> bool {
+        let passable = |a: Cell, b: Cell| match (self.height(a), self.height(b)) {
+            (Some(a), Some(b)) => (a - b).abs() <= self.max_step,
+            _ => false,
+        };
+
+        if from.0 != to.0 && from.1 != to.1 {
+            passable(from, (to.0, from.1)) && passable((to.0, from.1), to)
+                && passable(from, (from.0, to.1)) && passable((from.0, to.1), to)
+        } else {
+            passable(from, to)
+        }
+    }
+
+    fn search(&self, start: Cell, goal: Cell) -> Option<Vec<Cell>> {
+        let index = |cell: Cell| cell.1 * self.width + cell.0;
+        let heuristic = |cell: Cell| {
+            let dx = (cell.0 as f32 - goal.0 as f32).abs();
+            let dz = (cell.1 as f32 - goal.1 as f32).abs();
+            dx.max(dz) + (std::f32::consts::SQRT_2 - 1.0) * dx.min(dz)
+        };
+
+        let mut costs = vec![f32::INFINITY; self.heights.len()];
+        let mut came_from = vec![None; self.heights.len()];
+        let mut open = BinaryHeap::new();
+
+        costs[index(start)] = 0.0;
+        open.push(OpenCell { estimate: heuristic(start), cell: start });
+
+        while let Some(OpenCell { estimate, cell }) = open.pop() {
+            if cell == goal {
+                let mut path = vec![goal];
+                let mut current = goal;
+                while let Some(previous) = came_from[index(current)] {
+                    path.push(previous);
+                    current = previous;
+                }
+                path.reverse();
+                return Some(path);
+            }
+
+            let cost = costs[index(cell)];
+            // Skip entries made stale by a cheaper route found later.
+            if estimate > cost + heuristic(cell) {
+                continue;
+            }
+
+            for dz in -1..=1 {
+                for dx in -1..=1 {
+                    let neighbour = match self.offset(cell, dx, dz) {
+                        Some(neighbour) if neighbour != cell => neighbour,
+                        _ => continue,
+                    };
+                    if !self.can_step(cell, neighbour) {
+                        continue;
+                    }
+
+                    let step = if dx != 0 && dz != 0 { std::f32::consts::SQRT_2 } else { 1.0 };
+                    let new_cost = cost + step;
+                    if new_cost < costs[index(neighbour)] {
+                        costs[index(neighbour)] = new_cost;
+                        came_from[index(neighbour)] = Some(cell);
+                        open.push(OpenCell { estimate: new_cost + heuristic(neighbour), cell: neighbour });
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Walks the grid cells crossed by the segment between the centers of `a` and `b`.
+    fn line_of_sight(&self, a: Cell, b: Cell) -> bool {
+        let (mut x, mut z) = (a.0 as isize, a.1 as isize);
+        let (dx, dz) = (b.0 as isize - x, b.1 as isize - z);
+        let (nx, nz) = (dx.abs(), dz.abs());
+        let (sx, sz) = (dx.signum(), dz.signum());
+        let (mut ix, mut iz) = (0, 0);
+
+        while ix < nx || iz < nz {
+            let from = (x as usize, z as usize);
+
+            // Which cell border the segment crosses next: negative for X, positive for Z
+            // and zero when it goes exactly through a corner.
+            let decision = (1 + 2 * ix) * nz - (1 + 2 * iz) * nx;
+            if decision == 0 {
+                x += sx;
+                z += sz;
+                ix += 1;
+                iz += 1;
+            } else if decision < 0 {
+                x += sx;
+                ix += 1;
+            } else {
+                z += sz;
+                iz += 1;
+            }
+
+            if !self.can_step(from, (x as usize, z as usize)) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Drops every cell that can be skipped by walking straight to a later one.
+    fn smooth(&self, cells: &[Cell]) -> Vec<Cell> {
+        let mut smoothed = vec![cells[0]];
+        let mut anchor = 0;
+
+        while anchor < cells.len() - 1 {
+            let next = (anchor + 1..cells.len())
+                .rev()
+                .find(|&i| self.line_of_sight(cells[anchor], cells[i]))
+                .unwrap_or(anchor + 1);
+
+            smoothed.push(cells[next]);
+            anchor = next;
+        }
+
+        smoothed
+    }
+}
+
+#[derive(PartialEq)]
+struct OpenCell {
+    estimate: f32,
+    cell: Cell,
+}
+
+impl Eq for OpenCell {}
+
+impl Ord for OpenCell {
+    // Reversed, so that `BinaryHeap` pops the lowest estimate first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimate
+            .partial_cmp(&self.estimate)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for OpenCell {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: usize = 12;
+
+    /// A box spanning x in [4, 8) and z in [2, 10), too tall to step onto.
+    fn in_box(x: f32, z: f32) -> bool {
+        (4.0..8.0).contains(&x) && (2.0..10.0).contains(&z)
+    }
+
+    fn navmesh_with_box() -> NavMesh {
+        let heights = (0..SIZE)
+            .flat_map(|z| (0..SIZE).map(move |x| (x, z)))
+            .map(|(x, z)| if in_box(x as f32, z as f32) { Some(2.0) } else { Some(0.0) })
+            .collect();
+
+        NavMesh::from_heights((0.0, 0.0), 1.0, SIZE, SIZE, heights, 0.5)
+    }
+
+    #[test]
+    fn path_goes_around_blocking_box() {
+        let navmesh = navmesh_with_box();
+        let from = Vector3::new(1.5, 0.0, 6.5);
+        let to = Vector3::new(10.5, 0.0, 6.5);
+
+        let path = navmesh.find_path(from, to).expect("no path around the box");
+
+        assert!(path.len() > 1, "path goes straight through the box: {:?}", path);
+
+        let last = path.last().unwrap();
+        assert!((last.x - to.x).abs() < 1e-4 && (last.z - to.z).abs() < 1e-4);
+
+        let mut previous = from;
+        for waypoint in path.iter() {
+            assert_eq!(waypoint.y, 0.0, "path climbs onto the box: {:?}", path);
+
+            for i in 0..=100 {
+                let point = previous.lerp(waypoint, i as f32 / 100.0);
+                assert!(!in_box(point.x, point.z), "path crosses the box at {:?}: {:?}", point, path);
+            }
+            previous = *waypoint;
+        }
+    }
+
+    #[test]
+    fn straight_path_when_unobstructed() {
+        let navmesh = navmesh_with_box();
+        let to = Vector3::new(10.5, 0.0, 11.5);
+
+        let path = navmesh.find_path(Vector3::new(1.5, 0.0, 11.5), to).unwrap();
+
+        assert_eq!(path.len(), 1);
+    }
+
+    #[test]
+    fn clearance_keeps_paths_off_walls() {
+        let navmesh = navmesh_with_box().with_clearance(0.5);
+
+        assert!(navmesh.height((3, 6)).is_none());
+        assert!(navmesh.height((2, 6)).is_some());
+        assert!(navmesh.find_path(Vector3::new(1.5, 0.0, 6.5), Vector3::new(10.5, 0.0, 6.5)).is_some());
+    }
+}
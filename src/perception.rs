@@ -0,0 +1,216 @@
+use crate::{
+    settings::PerceptionSettings,
+    character_body::CharacterBody,
+};
+use rg3d::{
+    engine::ColliderHandle,
+    scene::{Scene, physics::RayCastOptions},
+    core::{
+        algebra::Vector3,
+        math::ray::Ray,
+    },
+};
+
+/// Someone a bot may notice.
+#[derive(Debug, Clone, Copy)]
+pub struct Target {
+    pub position: Vector3<f32>,
+    pub collider: ColliderHandle,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Memory {
+    position: Vector3<f32>,
+    time: f64,
+}
+
+/// Decides what a bot knows about its target: whether it is seen right now,
+/// and where it was last seen or heard.
+pub struct Perception {
+    settings: PerceptionSettings,
+    memory: Option<Memory>,
+    /// Noise heard since the last update.
+    heard: Option<Vector3<f32>>,
+}
+
+impl Perception {
+    pub fn new(settings: PerceptionSettings) -> Self {
+        Self {
+            settings,
+            memory: None,
+            heard: None,
+        }
+    }
+
+    pub fn apply_settings(&mut self, settings: PerceptionSettings) {
+        self.settings = settings;
+    }
+
+    /// Registers a noise; it is noticed on the next update if it is close enough.
+    pub fn hear(&mut self, position: Vector3<f32>) {
+        self.heard = Some(position);
+    }
+
+    /// Returns the position of `target` if it is seen during this tick.
+    pub fn update(&mut self,
+                  scene: &Scene,
+                  body: &CharacterBody,
+                  time: f64,
+                  target: Option<Target>) -> Option<Vector3<f32>> {
+        let position = scene.graph[body.pivot].global_position();
+
+        let seen = target
+            .filter(|target| self.can_see(scene, body, position, target))
+            .map(|target| target.position);
+
+        self.remember(time, position, seen);
+
+        seen
+    }
+
+    /// Remembers what was `seen` and the noise heard from `position`, and
+    /// forgets what is too old.
+    fn remember(&mut self, time: f64, position: Vector3<f32>, seen: Option<Vector3<f32>>) {
+        if let Some(noise) = self.heard.take() {
+            if (noise - position).norm() <= self.settings.hearing_radius {
+                self.memory = Some(Memory { position: noise, time });
+            }
+        }
+
+        if let Some(seen) = seen {
+            self.memory = Some(Memory { position: seen, time });
+        }
+
+        let memory_duration = self.settings.memory as f64;
+        if self.memory.map_or(false, |memory| time - memory.time > memory_duration) {
+            self.memory = None;
+        }
+    }
+
+    pub fn last_known_position(&self) -> Option<Vector3<f32>> {
+        self.memory.map(|memory| memory.position)
+    }
+
+    pub fn forget(&mut self) {
+        self.memory = None;
+    }
+
+    fn can_see(&self, scene: &Scene, body: &CharacterBody, position: Vector3<f32>, target: &Target) -> bool {
+        let eye_offset = Vector3::new(0.0, self.settings.eye_height, 0.0);
+        let eye = position + eye_offset;
+        let to_target = target.position + eye_offset - eye;
+
+        let rotation = scene.physics.bodies.get(&body.body).unwrap().position().rotation;
+        if !self.in_view(rotation * Vector3::z(), to_target) {
+            return false;
+        }
+
+        let mut intersections = Vec::new();
+        scene.physics.cast_ray(
+            RayCastOptions {
+                ray: Ray::new(eye, to_target),
+                max_len: to_target.norm(),
+                groups: Default::default(),
+                sort_results: true,
+            },
+            &mut intersections,
+        );
+
+        // Visible unless something other than the target is in the way.
+        intersections.iter()
+            .find(|intersection| intersection.collider != body.collider)
+            .map_or(true, |intersection| intersection.collider == target.collider)
+    }
+
+    /// Whether something `to_target` away is within the view distance and, seen
+    /// from above, inside the vision cone around `forward`.
+    fn in_view(&self, forward: Vector3<f32>, to_target: Vector3<f32>) -> bool {
+        if to_target.norm() > self.settings.view_distance {
+            return false;
+        }
+
+        let horizontal = Vector3::new(to_target.x, 0.0, to_target.z);
+        match horizontal.try_normalize(f32::EPSILON) {
+            Some(direction) => {
+                let angle = forward.dot(&direction).clamp(-1.0, 1.0).acos();
+                angle <= self.settings.fov.to_radians() / 2.0
+            }
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn perception() -> Perception {
+        Perception::new(PerceptionSettings {
+            fov: 90.0,
+            view_distance: 10.0,
+            eye_height: 1.5,
+            hearing_radius: 8.0,
+            memory: 5.0,
+        })
+    }
+
+    #[test]
+    fn only_what_is_in_the_vision_cone_is_in_view() {
+        let perception = perception();
+        let forward = Vector3::z();
+
+        assert!(perception.in_view(forward, Vector3::new(0.0, 0.0, 5.0)));
+        assert!(perception.in_view(forward, Vector3::new(4.0, 0.0, 5.0)));
+        assert!(perception.in_view(forward, Vector3::new(-4.0, 1.0, 5.0)));
+        assert!(!perception.in_view(forward, Vector3::new(6.0, 0.0, 5.0)));
+        assert!(!perception.in_view(forward, Vector3::new(0.0, 0.0, -5.0)));
+        // Straight above or below, there is no direction to compare.
+        assert!(perception.in_view(forward, Vector3::new(0.0, 3.0, 0.0)));
+    }
+
+    #[test]
+    fn nothing_is_in_view_past_the_view_distance() {
+        let perception = perception();
+        let forward = Vector3::z();
+
+        assert!(perception.in_view(forward, Vector3::new(0.0, 0.0, 9.9)));
+        assert!(!perception.in_view(forward, Vector3::new(0.0, 0.0, 10.1)));
+        assert!(!perception.in_view(forward, Vector3::new(0.0, 8.0, 8.0)));
+    }
+
+    #[test]
+    fn noises_are_heard_within_the_hearing_radius() {
+        let mut perception = perception();
+        let position = Vector3::new(1.0, 0.0, 1.0);
+
+        perception.hear(Vector3::new(10.0, 0.0, 1.0));
+        perception.remember(0.0, position, None);
+        assert_eq!(perception.last_known_position(), None);
+
+        perception.hear(Vector3::new(8.0, 0.0, 1.0));
+        perception.remember(0.1, position, None);
+        assert_eq!(perception.last_known_position(), Some(Vector3::new(8.0, 0.0, 1.0)));
+
+        // Each noise is heard once, and what is seen wins over what is heard.
+        perception.hear(Vector3::new(2.0, 0.0, 1.0));
+        perception.remember(0.2, position, Some(Vector3::new(3.0, 0.0, 3.0)));
+        assert_eq!(perception.last_known_position(), Some(Vector3::new(3.0, 0.0, 3.0)));
+    }
+
+    #[test]
+    fn memory_expires() {
+        let mut perception = perception();
+        let target = Vector3::new(3.0, 0.0, 3.0);
+
+        perception.remember(1.0, Vector3::zeros(), Some(target));
+        perception.remember(6.0, Vector3::zeros(), None);
+        assert_eq!(perception.last_known_position(), Some(target));
+
+        perception.remember(6.5, Vector3::zeros(), None);
+        assert_eq!(perception.last_known_position(), None);
+
+        perception.remember(7.0, Vector3::zeros(), Some(target));
+        perception.forget();
+        assert_eq!(perception.last_known_position(), None);
+    }
+}
@@ -1,24 +1,26 @@
 use crate::{
-    SETTINGS,
     GameTime,
+    settings::Settings,
     attached_camera::AttachedCamera,
     character::Character, 
-    character_animation::{CharacterAnimations, CharacterAnimationController},
+    character_animation::CharacterAnimationController,
     character_body::CharacterBody,
     weapon::Weapon,
-    request_model, character_body, character_animations,
-    message::Message,
+    resource_helper::CharacterResources,
+    message::{Message, MessageHandler},
+    error::{GameError, GameResult},
     movement_controller::MovementControlelr,
-    keyboard_input::Action,
+    controls::Controls,
+    keyboard_input::{Action, InputEvent},
 };
 use rg3d::{
-    engine::resource_manager::ResourceManager,
+    engine::ColliderHandle,
+    resource::model::Model,
     scene::{Scene, node::Node},
     core::{
         algebra::Vector3,
         pool::Handle,
     },
-    event::{Event, WindowEvent, ElementState, MouseButton},
 };
 use std::{
     ops::{Deref, DerefMut},
@@ -30,9 +32,10 @@ pub struct Player {
     pub weapon: Weapon,
     pub camera: AttachedCamera,
     pub movement_controller: MovementControlelr,
-    is_attacking: bool,
-    attack_duration: u128,
-    attack_start_time: u128,
+    pub controls: Controls,
+    controls_enabled: bool,
+    footstep_interval: f32,
+    last_footstep_time: f64,
 }
 
 impl Deref for Player {
@@ -50,21 +53,28 @@ impl DerefMut for Player {
 }
 
 impl Player {
-    pub async fn new(
+    pub fn new(
         scene: &mut Scene,
-        resource_manager: &ResourceManager,
+        settings: &Settings,
+        resources: &CharacterResources,
+        weapon_model: Option<Model>,
         sender: Sender<Message>,
-    ) -> Self {
-        let settings = &SETTINGS.read().unwrap();
+    ) -> GameResult<Self> {
+        let controls = Controls::new(settings.keymap.clone());
 
-        let body = character_body!(resource_manager, scene, player, Vector3::new(0.0, 0.0, 0.0));
+        let body = CharacterBody::from_resources(scene, resources, Vector3::new(0.0, 0.0, 0.0))?;
         let hand_node = scene.graph.find_by_name(body.model, &settings.player.hand_node);
-        if hand_node.is_none() {
-            panic!("hand node not found");
+        if resources.model.is_some() && hand_node.is_none() {
+            return Err(GameError::MissingNode {
+                key: "player.hand_node".to_owned(),
+                node: settings.player.hand_node.clone(),
+                model: resources.model_settings.model.clone(),
+            });
         }
 
-        let animations = character_animations!(scene, resource_manager, &body, player, settings);
-        let animation_controller = CharacterAnimationController::new(animations);
+        let animation_controller = CharacterAnimationController::from_resources(scene, &body, resources)?;
+
+        let weapon = Weapon::new(scene, settings, weapon_model, hand_node);
 
         let settings = &settings.player;
 
@@ -73,60 +83,101 @@ impl Player {
                                          body.pivot.clone(),
                                          &settings.camera);
 
-        let character = Character::new(scene, body, animation_controller);
+        let character = Character::new(scene, body, animation_controller,
+                                       &settings.health, sender);
 
         scene.graph.link_nodes(character.body.model, camera.camera.pivot);
 
         let movement_controller = MovementControlelr::new(settings.speed.clone());
 
-        let weapon = Weapon::new(scene, resource_manager, sender, hand_node).await;
-
-        Self {
+        Ok(Self {
             character,
             camera,
             movement_controller,
+            controls,
             weapon,
-            is_attacking: false,
-            attack_duration: 1000,
-            attack_start_time: 0,
-        }
+            controls_enabled: true,
+            footstep_interval: settings.footstep_interval,
+            last_footstep_time: 0.0,
+        })
     }
 
-    pub fn process_input_event(&mut self, event: &Event<()>) {
+    pub fn process_input_event(&mut self, event: &InputEvent) {
+        if !self.controls_enabled {
+            return;
+        }
+
         self.camera.process_input_event(event);
 
-        self.movement_controller.process_input_event(event);
+        self.controls.process_input_event(event);
+    }
 
-        if let Event::WindowEvent { event, .. } = event {
-            if let &WindowEvent::MouseInput { state, button, .. } = event {
-                let state = state == ElementState::Pressed;
-                if button == MouseButton::Left {
-                    // TODO: generalize movement_controller
-                    *self.movement_controller.actions.entry(Action::Attack).or_insert(false) = state;
-                }
-            }
-        }
+    /// Adopts the values of `settings` that can change while the game runs.
+    pub fn apply_settings(&mut self, scene: &mut Scene, settings: &Settings) {
+        self.movement_controller.apply_settings(settings.player.speed.clone());
+        self.controls.apply_settings(settings.keymap.clone());
+        self.camera.apply_settings(scene, &settings.player.camera);
+        self.weapon.stats = settings.weapon;
+        self.footstep_interval = settings.player.footstep_interval;
     }
 
-    pub fn update(&mut self, scene: &mut Scene, time: GameTime) {
-        self.camera.update(scene);
+    /// Returns colliders struck by the weapon during this tick.
+    pub fn update(&mut self, scene: &mut Scene, time: GameTime) -> Vec<ColliderHandle> {
+        self.controls.update(time.elapsed);
+        self.camera.turn(self.controls.look(), time.delta);
+        self.camera.update(scene, time.delta);
 
         let mut animation_input = self.movement_controller.update(scene,
                                                                   self.camera.camera.pivot,
-                                                                  &mut self.character.body);
+                                                                  &mut self.character.body,
+                                                                  &self.controls);
+
+        if self.controls.held(Action::Attack) && self.weapon.start_swing(time) {
+            let position = scene.graph[self.character.body.pivot].global_position();
+            self.character.send(Message::AttackStarted {
+                attacker: self.character.body.body,
+                position,
+            });
+        }
 
-        let elapsed_time = time.clock.elapsed().as_millis();
+        animation_input.attacking = self.weapon.is_swinging(time);
 
-        self.is_attacking = self.is_attacking && !(elapsed_time - self.attack_start_time >= self.attack_duration);
+        if animation_input.running
+            && time.elapsed - self.last_footstep_time >= self.footstep_interval as f64
+            && self.character.body.has_ground_contact(&scene.physics) {
+            self.last_footstep_time = time.elapsed;
 
-        if !self.is_attacking && self.movement_controller.action_state(Action::Attack) {
-            self.is_attacking = true;
-            self.attack_start_time = elapsed_time;
+            let position = scene.graph[self.character.body.pivot].global_position();
+            self.character.send(Message::Footstep {
+                source: self.character.body.body,
+                position,
+            });
         }
 
-        animation_input.attacking = self.is_attacking;
+        let struck = self.weapon.update(scene,
+                                        time,
+                                        self.character.body.pivot,
+                                        self.character.body.collider);
 
         self.character.update(scene, time, animation_input);
+
+        struck
     }
 
 }
+
+impl MessageHandler for Player {
+    fn handle_message(&mut self, message: &Message) {
+        match message {
+            Message::CharacterDied { body } if *body == self.character.body.body => {
+                self.controls_enabled = false;
+            }
+            Message::LevelFinished => {
+                self.controls_enabled = false;
+            }
+            _ => return,
+        }
+        self.controls.clear();
+        self.camera.clear();
+    }
+}
@@ -0,0 +1,320 @@
+use crate::{
+    keyboard_input::{Action, Binding, Input, InputEvent, KeyMap, Modifiers},
+    error::{GameError, GameResult},
+};
+use rg3d::event::VirtualKeyCode;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// The new binding replaces all bindings of the action.
+    Replace,
+    /// The new binding is added to those of the action.
+    Add,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Bound { action: Action, binding: Binding },
+    /// `binding` is already used by `actions`; the rebinder keeps waiting for another input.
+    Conflict { binding: Binding, actions: Vec<Action> },
+    Cancelled,
+}
+
+/// Edits a keymap by waiting for the input to bind.
+pub struct Rebinder {
+    keymap: KeyMap,
+    defaults: KeyMap,
+    waiting: Option<(Action, Mode)>,
+    /// Modifiers held since waiting started.
+    modifiers: Modifiers,
+    /// Modifier key pressed with nothing else yet; it is bound itself if released first.
+    lone_modifier: Option<VirtualKeyCode>,
+}
+
+impl Rebinder {
+    pub fn new(keymap: KeyMap, defaults: KeyMap) -> Self {
+        Self {
+            keymap,
+            defaults,
+            waiting: None,
+            modifiers: Default::default(),
+            lone_modifier: None,
+        }
+    }
+
+    pub fn bindings(&self, action: Action) -> &[Binding] {
+        self.keymap.bindings.get(&action).map_or(&[], Vec::as_slice)
+    }
+
+    /// Action whose binding is awaited.
+    pub fn waiting(&self) -> Option<Action> {
+        self.waiting.map(|(action, _)| action)
+    }
+
+    /// Binds the next input to `action`.
+    pub fn start(&mut self, action: Action, mode: Mode) {
+        self.waiting = Some((action, mode));
+        self.modifiers = Default::default();
+        self.lone_modifier = None;
+    }
+
+    /// While waiting, turns `event` into a binding. Escape cancels.
+    pub fn process_input_event(&mut self, event: &InputEvent) -> Option<Outcome> {
+        let (action, mode) = self.waiting?;
+
+        let input = match *event {
+            InputEvent::Key { code: VirtualKeyCode::Escape, pressed: true } => {
+                self.waiting = None;
+                return Some(Outcome::Cancelled);
+            }
+            InputEvent::Key { code, pressed } => {
+                if let Some(set) = modifier(&mut self.modifiers, code) {
+                    *set = pressed;
+                    if pressed {
+                        self.lone_modifier = Some(code);
+                        return None;
+                    } else if self.lone_modifier == Some(code) {
+                        // Bind the modifier key on its own, without itself as a modifier.
+                        self.modifiers = Default::default();
+                    } else {
+                        return None;
+                    }
+                } else if !pressed {
+                    return None;
+                }
+                Input::Key(code)
+            }
+            InputEvent::MouseButton { button, pressed: true } => Input::Mouse(button),
+            InputEvent::Wheel { delta } if delta > 0.0 => Input::WheelUp,
+            InputEvent::Wheel { .. } => Input::WheelDown,
+            InputEvent::GamepadButton { button, pressed: true } => Input::Gamepad(button),
+            InputEvent::GamepadAxis { axis, value } if value > self.keymap.gamepad.trigger_threshold => {
+                // Sticks drive movement and the camera, only triggers can be bound.
+                Input::Gamepad(axis.trigger_button()?)
+            }
+            InputEvent::MouseButton { .. }
+            | InputEvent::MouseMotion { .. }
+            | InputEvent::GamepadButton { .. }
+            | InputEvent::GamepadAxis { .. }
+            | InputEvent::ReleaseAll => return None,
+        };
+        self.lone_modifier = None;
+
+        let binding = Binding { modifiers: self.modifiers, input };
+
+        let actions = self.conflicts(binding, action);
+        if !actions.is_empty() {
+            return Some(Outcome::Conflict { binding, actions });
+        }
+
+        let bindings = self.keymap.bindings.entry(action).or_default();
+        if mode == Mode::Replace {
+            bindings.clear();
+        }
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+
+        self.waiting = None;
+        Some(Outcome::Bound { action, binding })
+    }
+
+    /// Actions other than `action` that `binding` is bound to.
+    pub fn conflicts(&self, binding: Binding, action: Action) -> Vec<Action> {
+        let mut actions = self.keymap.bindings
+            .iter()
+            .filter(|(other, bindings)| **other != action && bindings.contains(&binding))
+            .map(|(other, _)| *other)
+            .collect::<Vec<_>>();
+        actions.sort_unstable();
+        actions
+    }
+
+    /// Removes the last binding of `action`, unless it is the only one.
+    pub fn remove_last(&mut self, action: Action) -> Option<Binding> {
+        let bindings = self.keymap.bindings.get_mut(&action)?;
+        if bindings.len() > 1 {
+            bindings.pop()
+        } else {
+            None
+        }
+    }
+
+    pub fn reset(&mut self, action: Action) {
+        match self.defaults.bindings.get(&action) {
+            Some(bindings) => self.keymap.bindings.insert(action, bindings.clone()),
+            None => self.keymap.bindings.remove(&action),
+        };
+    }
+
+    pub fn reset_all(&mut self) {
+        self.keymap.bindings = self.defaults.bindings.clone();
+    }
+
+    /// Writes the bindings of every action to `path`, in the layout of `settings/keymap.toml`.
+    pub fn save(&self, path: &Path) -> GameResult<()> {
+        #[derive(Serialize)]
+        struct File<'a> {
+            keymap: Bindings<'a>,
+        }
+
+        #[derive(Serialize)]
+        struct Bindings<'a> {
+            bindings: BTreeMap<String, &'a [Binding]>,
+        }
+
+        let bindings = Action::ALL
+            .iter()
+            .map(|action| (format!("{:?}", action), self.bindings(*action)))
+            .collect();
+
+        let error = |reason: String| GameError::Save { path: path.to_owned(), reason };
+        let contents = toml::to_string_pretty(&File { keymap: Bindings { bindings } })
+            .map_err(|e| error(e.to_string()))?;
+
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory).map_err(|e| error(e.to_string()))?;
+        }
+        fs::write(path, contents).map_err(|e| error(e.to_string()))
+    }
+}
+
+/// The flag of `modifiers` that `code` sets, if it is a modifier key.
+fn modifier(modifiers: &mut Modifiers, code: VirtualKeyCode) -> Option<&mut bool> {
+    match code {
+        VirtualKeyCode::LControl | VirtualKeyCode::RControl => Some(&mut modifiers.ctrl),
+        VirtualKeyCode::LShift | VirtualKeyCode::RShift => Some(&mut modifiers.shift),
+        VirtualKeyCode::LAlt | VirtualKeyCode::RAlt => Some(&mut modifiers.alt),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        keyboard_input::key,
+        gamepad::{GamepadAxis, GamepadButton},
+    };
+    use rg3d::event::MouseButton;
+    use std::{collections::HashMap, convert::TryFrom};
+
+    fn binding(source: &str) -> Binding {
+        Binding::try_from(source.to_owned()).unwrap()
+    }
+
+    fn rebinder() -> Rebinder {
+        let defaults = KeyMap::with_bindings(&[(Action::Forward, "W"), (Action::Jump, "Space")]);
+        Rebinder::new(defaults.clone(), defaults)
+    }
+
+    #[test]
+    fn next_input_is_bound_with_its_modifiers() {
+        let mut rebinder = rebinder();
+        rebinder.start(Action::Forward, Mode::Replace);
+
+        assert_eq!(rebinder.process_input_event(&key(VirtualKeyCode::LControl, true)), None);
+        assert_eq!(rebinder.process_input_event(&key(VirtualKeyCode::Up, true)),
+                   Some(Outcome::Bound { action: Action::Forward, binding: binding("Ctrl+Up") }));
+        assert_eq!(rebinder.bindings(Action::Forward), &[binding("Ctrl+Up")]);
+        assert_eq!(rebinder.waiting(), None);
+    }
+
+    #[test]
+    fn modifier_keys_can_be_bound_alone() {
+        let mut rebinder = rebinder();
+        rebinder.start(Action::Jump, Mode::Add);
+
+        rebinder.process_input_event(&key(VirtualKeyCode::LShift, true));
+        rebinder.process_input_event(&key(VirtualKeyCode::LShift, false));
+
+        assert_eq!(rebinder.bindings(Action::Jump), &[binding("Space"), binding("LShift")]);
+    }
+
+    #[test]
+    fn conflicts_keep_waiting() {
+        let mut rebinder = rebinder();
+        rebinder.start(Action::Forward, Mode::Add);
+
+        assert_eq!(rebinder.process_input_event(&key(VirtualKeyCode::Space, true)),
+                   Some(Outcome::Conflict { binding: binding("Space"), actions: vec![Action::Jump] }));
+        assert_eq!(rebinder.waiting(), Some(Action::Forward));
+
+        rebinder.process_input_event(&InputEvent::Wheel { delta: 1.0 });
+        assert_eq!(rebinder.bindings(Action::Forward), &[binding("W"), binding("WheelUp")]);
+    }
+
+    #[test]
+    fn gamepad_buttons_and_triggers_can_be_bound() {
+        let mut rebinder = rebinder();
+        let axis = |axis, value| InputEvent::GamepadAxis { axis, value };
+
+        rebinder.start(Action::Jump, Mode::Replace);
+        rebinder.process_input_event(&InputEvent::GamepadButton { button: GamepadButton::South, pressed: true });
+        assert_eq!(rebinder.bindings(Action::Jump), &[binding("PadSouth")]);
+
+        rebinder.start(Action::Forward, Mode::Add);
+        assert_eq!(rebinder.process_input_event(&axis(GamepadAxis::LeftStickY, 1.0)), None);
+        assert_eq!(rebinder.process_input_event(&axis(GamepadAxis::RightTrigger, 0.3)), None);
+        rebinder.process_input_event(&axis(GamepadAxis::RightTrigger, 0.7));
+        assert_eq!(rebinder.bindings(Action::Forward), &[binding("W"), binding("PadRightTrigger")]);
+    }
+
+    #[test]
+    fn escape_cancels() {
+        let mut rebinder = rebinder();
+        rebinder.start(Action::Forward, Mode::Replace);
+
+        assert_eq!(rebinder.process_input_event(&key(VirtualKeyCode::Escape, true)), Some(Outcome::Cancelled));
+        assert_eq!(rebinder.bindings(Action::Forward), &[binding("W")]);
+    }
+
+    #[test]
+    fn reset_restores_defaults() {
+        let mut rebinder = rebinder();
+        rebinder.start(Action::Forward, Mode::Add);
+        rebinder.process_input_event(&key(VirtualKeyCode::Up, true));
+        rebinder.start(Action::Jump, Mode::Replace);
+        rebinder.process_input_event(&key(VirtualKeyCode::J, true));
+
+        assert_eq!(rebinder.remove_last(Action::Jump), None);
+
+        rebinder.reset(Action::Forward);
+        assert_eq!(rebinder.bindings(Action::Forward), &[binding("W")]);
+        assert_eq!(rebinder.bindings(Action::Jump), &[binding("J")]);
+
+        rebinder.reset_all();
+        assert_eq!(rebinder.bindings(Action::Jump), &[binding("Space")]);
+    }
+
+    #[test]
+    fn saved_keymap_can_be_read_back() {
+        let mut rebinder = rebinder();
+        rebinder.start(Action::Forward, Mode::Add);
+        rebinder.process_input_event(&InputEvent::MouseButton { button: MouseButton::Right, pressed: true });
+
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("keymap.toml");
+        rebinder.save(&path).unwrap();
+
+        #[derive(Deserialize)]
+        struct File {
+            keymap: Bindings,
+        }
+
+        #[derive(Deserialize)]
+        struct Bindings {
+            bindings: HashMap<String, Vec<Binding>>,
+        }
+
+        let file: File = toml::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+
+        assert_eq!(file.keymap.bindings["Forward"], vec![binding("W"), binding("MouseRight")]);
+        assert_eq!(file.keymap.bindings["Jump"], vec![binding("Space")]);
+    }
+}
@@ -1,48 +1,133 @@
-#[macro_export]
-macro_rules! request_resource {
-    ($resource_manager:expr, $type:ident, $($name:ident).+) => {
-        {
-            use crate::SETTINGS;
-
-            let settings = &SETTINGS.read().unwrap();
-            request_resource!($resource_manager, $type, $($name).+, settings)
-        }
-    };
-    ($resource_manager:expr, $type:ident, $($name:ident).+, $settings:ident) => {
-        {
-            use std::path::PathBuf;
-            use rg3d::engine::resource_manager::MaterialSearchOptions;
-
-            let file = &$settings.$type.$($name).+;
-            let path = PathBuf::from(&$settings.data_dir).join(stringify!($type)).join(file);
-            $resource_manager
-                .request_model(
-                    path,
-                    MaterialSearchOptions::MaterialsDirectory($settings.get_materials_path()),
-                    )
-                .await
-                .unwrap()
-        }
-    };
+use crate::{
+    assets::{AssetFile, AssetRegistry, CharacterAsset},
+    error::{GameError, GameResult},
+    settings::{Settings, CharacterModel, AnimationSettings},
+};
+use rg3d::{
+    core::futures::future::{join, join_all},
+    engine::resource_manager::{MaterialSearchOptions, ResourceManager},
+    resource::model::Model,
+    utils::log::{Log, MessageKind},
+};
+use std::{
+    collections::HashMap,
+    future::Future,
+    time::Instant,
+};
+
+/// Loads a model or a scene declared in settings.
+pub async fn request_asset(resource_manager: &ResourceManager,
+                           settings: &Settings,
+                           file: &AssetFile) -> GameResult<Model> {
+    resource_manager
+        .request_model(
+            &file.path,
+            MaterialSearchOptions::MaterialsDirectory(settings.get_materials_path()),
+            )
+        .await
+        .map_err(|error| GameError::ResourceLoad {
+            key: file.key.clone(),
+            reason: format!("{:?}", error),
+            path: file.path.clone(),
+        })
+}
+
+/// Awaits `future` and logs how long it took.
+pub async fn timed<F: Future>(name: &str, future: F) -> F::Output {
+    let start = Instant::now();
+    let output = future.await;
+    Log::writeln(MessageKind::Information,
+                 format!("Loaded {} in {:?}", name, start.elapsed()));
+    output
 }
 
-#[macro_export]
-macro_rules! request_model {
-    ($resource_manager:expr, $($name:ident).+ $(, $settings:ident)?) => {
-        crate::request_resource!($resource_manager, models, $($name).+ $(, $settings)?)
-    };
+pub struct AnimationResource {
+    pub model: Model,
+    pub file: AssetFile,
 }
 
-#[macro_export]
-macro_rules! request_scene {
-    ($resource_manager:expr, $($name:ident).+ $(, $settings:ident)?) => {
-        crate::request_resource!($resource_manager, scenes, $($name).+ $(, $settings)?)
-    };
+/// Everything needed to instantiate a character, loaded once and shared
+/// between all instances.
+pub struct CharacterResources {
+    pub name: String,
+    /// Where `model_settings` and `animation_settings` are found, used in error messages.
+    pub model_key: String,
+    pub animations_key: String,
+    /// Missing for characters simulated without a look, see `bare`.
+    pub model: Option<Model>,
+    pub model_settings: CharacterModel,
+    /// Empty when `model` is missing.
+    pub animations: HashMap<String, AnimationResource>,
+    pub animation_settings: AnimationSettings,
 }
 
-#[macro_export]
-macro_rules! request_animation {
-    ($resource_manager:expr, $($name:ident).+ $(, $settings:ident)?) => {
-        crate::request_resource!($resource_manager, animations, $($name).+ $(, $settings)?)
-    };
+impl CharacterResources {
+    pub async fn load(resource_manager: &ResourceManager,
+                      settings: &Settings,
+                      asset: &CharacterAsset<'_>) -> GameResult<Self> {
+        let model_request = request_asset(resource_manager, settings, &asset.model_file);
+
+        let animation_requests = join_all(asset.animation_files.iter().map(|(state, file)| {
+            async move {
+                let model = request_asset(resource_manager, settings, file).await?;
+                Ok::<_, GameError>((state.clone(), AnimationResource { model, file: file.clone() }))
+            }
+        }));
+
+        let (model_resource, animation_resources) = join(model_request, animation_requests).await;
+
+        Ok(Self {
+            name: asset.name.clone(),
+            model_key: asset.model_key.clone(),
+            animations_key: asset.animations_key.clone(),
+            model: Some(model_resource?),
+            model_settings: asset.model.clone(),
+            animations: animation_resources.into_iter().collect::<GameResult<_>>()?,
+            animation_settings: asset.animations.clone(),
+        })
+    }
+
+    /// Resources without a model and animations, for runs in which nothing is
+    /// rendered. Such characters move, fight and collide like any other.
+    pub fn bare(asset: &CharacterAsset<'_>) -> Self {
+        Self {
+            name: asset.name.clone(),
+            model_key: asset.model_key.clone(),
+            animations_key: asset.animations_key.clone(),
+            model: None,
+            model_settings: asset.model.clone(),
+            animations: HashMap::new(),
+            animation_settings: asset.animations.clone(),
+        }
+    }
+}
+
+/// Loads the resources of every bot archetype, keyed by archetype name.
+pub async fn load_bot_archetypes(resource_manager: &ResourceManager,
+                                 settings: &Settings) -> GameResult<HashMap<String, CharacterResources>> {
+    let assets = AssetRegistry::new(settings);
+
+    let archetypes = assets.bot_archetypes()
+        .into_iter()
+        .map(|name| assets.character(name))
+        .collect::<GameResult<Vec<_>>>()?;
+
+    let requests = archetypes.iter().map(|asset| {
+        async move {
+            let resources = timed(&asset.name, CharacterResources::load(resource_manager, settings, asset)).await?;
+            Ok::<_, GameError>((asset.name.clone(), resources))
+        }
+    });
+
+    join_all(requests).await.into_iter().collect()
+}
+
+/// Bare resources of every bot archetype, keyed by archetype name.
+pub fn bare_bot_archetypes(settings: &Settings) -> GameResult<HashMap<String, CharacterResources>> {
+    let assets = AssetRegistry::new(settings);
+
+    assets.bot_archetypes()
+        .into_iter()
+        .map(|name| Ok::<_, GameError>((name.to_owned(), CharacterResources::bare(&assets.character(name)?))))
+        .collect()
 }
@@ -13,7 +13,9 @@ pub struct RotatingCamera {
     pub pivot: Handle<Node>,
     pub hinge: Handle<Node>,
 
+    /// Degrees.
     pub yaw: f32,
+    /// Degrees, positive looking down.
     pub pitch: f32,
 }
 
@@ -45,4 +47,13 @@ impl RotatingCamera {
             yaw: 0.0, pitch: 0.0,
         }
     }
+
+    pub fn apply_settings(&self, scene: &mut Scene, settings: &CameraSettings) {
+        scene.graph[self.camera]
+            .local_transform_mut()
+            .set_position(settings.get_offset());
+        scene.graph[self.hinge]
+            .local_transform_mut()
+            .set_position(settings.get_hinge_offset());
+    }
 }
@@ -1,11 +1,19 @@
-use crate::keyboard_input::KeyMap;
+use crate::{
+    keyboard_input::KeyMap,
+    bot_ai::BotBehavior,
+};
 use rg3d::core::algebra::Vector3;
-use config::{ConfigError, Config, File};
-use std::path::PathBuf;
+use config::{ConfigError, Config, Environment, File, FileFormat};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    fs,
+    path::{Path, PathBuf},
+};
 
 pub type CharacterSize = (f32, f32);
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct CharacterModel {
     pub model: String,
     pub spine: String,
@@ -19,27 +27,57 @@ pub struct WeaponModel { // TODO: duplication
     pub scale: f32,
 }
 
+#[derive(Debug, Deserialize, Clone, Copy)]
+pub struct WeaponSettings {
+    pub damage: f32,
+    pub reach: f32,
+    pub cooldown: f32,
+    pub swing_duration: f32,
+    pub active_window: (f32, f32),
+    pub arc: f32,
+    pub arc_rays: u32,
+    pub height: f32,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Models {
     pub player: CharacterModel,
-    pub bot: CharacterModel,
     pub weapon: WeaponModel,
 }
 
 #[derive(Debug, Deserialize)]
-pub struct Scene {
-    pub model: String,
+pub struct NavMeshSettings {
+    /// XZ corners of the area covered by the navmesh.
+    pub min: (f32, f32),
+    pub max: (f32, f32),
+    /// Heights between which the ground is searched for.
+    pub height_range: (f32, f32),
+    pub cell_size: f32,
+    /// Largest height difference between neighbouring cells that can be walked over.
+    pub max_step: f32,
+    pub agent_radius: f32,
 }
 
 #[derive(Debug, Deserialize)]
-pub struct Scenes {
-    pub main: Scene,
+pub struct Scene {
+    pub model: String,
+    pub navmesh: NavMeshSettings,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct CameraSettings {
     pub offset: (f32, f32, f32),
     pub hinge_offset: (f32, f32, f32),
+    /// Degrees turned per mouse count, both horizontally and vertically.
+    pub sensitivity: f32,
+    /// Moving the mouse or the right stick up looks down.
+    pub invert_y: bool,
+    /// Seconds over which mouse motion is spread out; 0 turns smoothing off.
+    pub smoothing: f32,
+    /// Sensitivity added for every 1000 counts per second of mouse speed; 0 turns acceleration off.
+    pub acceleration: f32,
+    /// Lowest and highest pitch in degrees, negative looking up.
+    pub pitch_limits: (f32, f32),
 }
 
 
@@ -49,58 +87,384 @@ pub struct CharacterSpeedSettings {
     pub jump: f32,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
+pub struct HealthSettings {
+    pub max: f32,
+    pub invulnerability: f32,
+    /// Health regained per second; 0 turns regeneration off.
+    #[serde(default)]
+    pub regeneration: f32,
+    /// Seconds after taking damage before regeneration starts.
+    #[serde(default)]
+    pub regeneration_delay: f32,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct AnimationStateSettings {
+    pub file: String,
+    #[serde(default = "default_looped")]
+    pub looped: bool,
+    /// Rule that rewinds the animation to its start while true.
+    #[serde(default)]
+    pub rewind: Option<String>,
+}
+
+fn default_looped() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct AnimationTransitionSettings {
+    pub from: String,
+    pub to: String,
+    pub blend: f32,
+    pub rule: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
 pub struct AnimationSettings {
-    pub idle: String,
-    pub run: String,
-    pub jump: String,
-    pub attack: String,
+    pub entry: String,
+    pub states: HashMap<String, AnimationStateSettings>,
+    pub transitions: Vec<AnimationTransitionSettings>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Animations {
     pub player: AnimationSettings,
-    pub bot: AnimationSettings,
+    /// Animation sets that bot archetypes refer to by name.
+    pub bots: HashMap<String, AnimationSettings>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct PlayerSettings {
     pub camera: CameraSettings,
     pub speed: CharacterSpeedSettings,
+    pub health: HealthSettings,
     pub hand_node: String,
+    /// Seconds between footstep noises while running.
+    pub footstep_interval: f32,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct BotAiSettings {
+    pub attack_range: f32,
+    pub attack_cooldown: f32,
+    pub attack_duration: f32,
+    /// Seconds into the attack at which the damage is applied.
+    pub attack_hit_time: f32,
+    pub damage: f32,
+    pub arrive_distance: f32,
+    /// How far the destination may move before the path to it is recomputed.
+    pub repath_distance: f32,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct PerceptionSettings {
+    /// Full angle of the vision cone, in degrees.
+    pub fov: f32,
+    pub view_distance: f32,
+    /// Height of the eyes above the feet, for both the bot and its target.
+    pub eye_height: f32,
+    pub hearing_radius: f32,
+    /// Seconds after which the last known position of the target is forgotten.
+    pub memory: f32,
+}
+
+/// Weights are relative to the bot's run speed.
+#[derive(Debug, Deserialize, Clone)]
+pub struct SteeringSettings {
+    /// Distance from the destination at which the bot starts to slow down.
+    pub slowing_radius: f32,
+    /// Other bots within this distance influence the steering.
+    pub neighbour_radius: f32,
+    pub separation_radius: f32,
+    pub separation_weight: f32,
+    pub cohesion_weight: f32,
+    pub alignment_weight: f32,
+    /// Length of the feeler ray used to avoid obstacles.
+    pub avoidance_distance: f32,
+    pub avoidance_weight: f32,
+    /// Distance from the target at which a group of bots surrounds it.
+    pub surround_radius: f32,
+}
+
+/// A kind of enemy; bots are spawned by the name of their archetype.
+#[derive(Debug, Deserialize, Clone)]
+pub struct BotArchetype {
+    pub model: CharacterModel,
+    /// Key under `animations.bots`.
+    pub animations: String,
+    /// Key under `bot.behaviors`.
+    pub behavior: String,
+    pub speed: CharacterSpeedSettings,
+    pub health: HealthSettings,
+    pub ai: BotAiSettings,
+    pub perception: PerceptionSettings,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct BotSettings {
-    pub speed: CharacterSpeedSettings,
+    pub steering: SteeringSettings,
+    pub behaviors: HashMap<String, BotBehavior>,
+    pub archetypes: HashMap<String, BotArchetype>,
+}
+
+/// Per-wave increase of the bot multipliers; wave N gets `1 + value * (N - 1)`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct DifficultySettings {
+    pub health: f32,
+    pub damage: f32,
+    pub speed: f32,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct WaveSettings {
+    /// Seconds between the previous wave being cleared and this one starting.
+    pub delay: f32,
+    /// Number of bots of every archetype.
+    pub bots: BTreeMap<String, u32>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct WavesSettings {
+    /// Nodes of the level whose names start with this are used as spawn points.
+    pub marker_prefix: String,
+    /// Bots are spawned up to this far from their spawn point along X and Z.
+    pub spread: f32,
+    /// Used when the level has no spawn points.
+    pub fallback_area: (f32, f32),
+    pub fallback_height: f32,
+    pub scaling: DifficultySettings,
+    pub list: Vec<WaveSettings>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Settings {
     pub data_dir: String,
+    /// Fixed RNG seed; a random one is used when missing.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Key under `scenes` of the level to play.
+    pub level: String,
     pub models: Models,
-    pub scenes: Scenes,
+    pub scenes: HashMap<String, Scene>,
     pub animations: Animations,
     pub keymap: KeyMap,
     pub player: PlayerSettings,
     pub bot: BotSettings,
+    pub weapon: WeaponSettings,
+    pub waves: WavesSettings,
 }
 
-impl Settings {
-    pub fn new() -> Result<Self, ConfigError> {
+/// Files of the settings directory, each holding the top-level key of the same
+/// name, along with the contents they had at build time. Those contents are the
+/// built-in defaults, used in place of any file missing from the directory, so the
+/// game starts without the directory too.
+pub const FILES: &[(&str, &str)] = &[
+    ("settings", include_str!("../settings/settings.toml")),
+    ("models", include_str!("../settings/models.toml")),
+    ("animations", include_str!("../settings/animations.toml")),
+    ("scenes", include_str!("../settings/scenes.toml")),
+    ("keymap", include_str!("../settings/keymap.toml")),
+    ("player", include_str!("../settings/player.toml")),
+    ("bot", include_str!("../settings/bot.toml")),
+    ("weapon", include_str!("../settings/weapon.toml")),
+    ("waves", include_str!("../settings/waves.toml")),
+];
+
+/// Environment variables starting with this and `_` override settings;
+/// `__` separates the parts of a key, e.g. `JAM_PLAYER__SPEED__RUN=9`.
+const ENV_PREFIX: &str = "JAM";
+
+/// Where settings are read from. Each layer overrides the ones before it:
+/// `directory`, `user_keymap`, `user_file`, `JAM_*` environment variables and
+/// `overrides`. A file missing from `directory` is replaced by its built-in
+/// default; one that is there replaces the default entirely, so that entries
+/// deleted from it stay deleted.
+#[derive(Debug, Clone)]
+pub struct SettingsSources {
+    pub directory: PathBuf,
+    /// Bindings changed in game.
+    pub user_keymap: Option<PathBuf>,
+    pub user_file: Option<PathBuf>,
+    /// Whether `JAM_*` environment variables are merged.
+    pub environment: bool,
+    /// `key=value` pairs, e.g. from `--set`.
+    pub overrides: Vec<(String, String)>,
+}
+
+impl Default for SettingsSources {
+    fn default() -> Self {
+        let user_directory = user_directory();
+
+        Self {
+            directory: settings_directory(),
+            user_keymap: user_directory.as_ref().map(|directory| directory.join("keymap.toml")),
+            user_file: user_directory.map(|directory| directory.join("settings.toml")),
+            environment: true,
+            overrides: Vec::new(),
+        }
+    }
+}
+
+impl SettingsSources {
+    /// Only the files of the repository, so that tests don't depend on the
+    /// machine they run on.
+    #[cfg(test)]
+    pub fn repository() -> Self {
+        Self {
+            directory: PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("settings"),
+            user_keymap: None,
+            user_file: None,
+            environment: false,
+            overrides: Vec::new(),
+        }
+    }
+
+    /// Files that are merged if they exist, in order.
+    pub fn files(&self) -> Vec<PathBuf> {
+        FILES.iter()
+            .map(|(name, _)| self.directory.join(format!("{}.toml", name)))
+            .chain(self.user_keymap.clone())
+            .chain(self.user_file.clone())
+            .collect()
+    }
+
+    /// The keymap that in-game changes are reset to.
+    pub fn default_keymap(&self) -> Result<KeyMap, ConfigError> {
+        let sources = Self {
+            user_keymap: None,
+            ..self.clone()
+        };
+        Ok(Settings::load(&sources)?.keymap)
+    }
+
+    pub fn merge(&self) -> Result<Config, ConfigError> {
         let mut s = Config::default();
-        s.merge(File::with_name("settings/settings"))?;
-        s.merge(File::with_name("settings/models"))?;
-        s.merge(File::with_name("settings/animations"))?;
-        s.merge(File::with_name("settings/scenes"))?;
-        s.merge(File::with_name("settings/keymap"))?;
-        s.merge(File::with_name("settings/player"))?;
-        s.merge(File::with_name("settings/bot"))?;
+        for (name, defaults) in FILES {
+            let file = self.directory.join(format!("{}.toml", name));
+            if file.is_file() {
+                s.merge(File::from(file))?;
+            } else {
+                s.merge(File::from_str(defaults, FileFormat::Toml))?;
+            }
+        }
+        for file in self.user_keymap.iter().chain(self.user_file.iter()) {
+            s.merge(File::from(file.as_path()).required(false))?;
+        }
+        if self.environment {
+            s.merge(Environment::with_prefix(ENV_PREFIX).separator("__"))?;
+        }
+        for (key, value) in self.overrides.iter() {
+            s.set(key, value.as_str())?;
+        }
+
+        Ok(s)
+    }
+
+    /// The last layer that sets `key`, e.g. `settings/player.toml`,
+    /// `JAM_PLAYER__SPEED__RUN` or `--set player.speed.run`.
+    pub fn origin_of(&self, key: &str) -> String {
+        if let Some((set, _)) = self.overrides.iter().rev().find(|(set, _)| covers(set, key)) {
+            return format!("--set {}", set);
+        }
+
+        if self.environment {
+            let prefix = format!("{}_", ENV_PREFIX);
+            let variable = env::vars()
+                .map(|(name, _)| name)
+                .find(|name| name.strip_prefix(&prefix).map_or(false, |set| covers(&set.replace("__", "."), key)));
+            if let Some(variable) = variable {
+                return variable;
+            }
+        }
 
-        s.try_into()
+        let user_files = self.user_file.iter().chain(self.user_keymap.iter());
+        if let Some(file) = user_files.into_iter().find(|file| defines(file, key)) {
+            return file.display().to_string();
+        }
+
+        let top = key.split(&['.', '['][..]).next().unwrap_or_default();
+        let name = FILES.iter()
+            .map(|(name, _)| name)
+            .find(|name| **name == top)
+            .unwrap_or(&"settings");
+        let file = self.directory.join(format!("{}.toml", name));
+        if file.is_file() {
+            file.display().to_string()
+        } else {
+            format!("built-in {}.toml", name)
+        }
+    }
+
+    /// The merged settings of all layers, as TOML.
+    pub fn effective(&self) -> Result<String, ConfigError> {
+        let value: toml::Value = self.merge()?.try_into()?;
+        toml::to_string_pretty(&value).map_err(|error| ConfigError::Message(error.to_string()))
     }
 }
 
+/// True if setting `set` also sets `key`. Environment variables are
+/// lowercased, so the comparison ignores case.
+fn covers(set: &str, key: &str) -> bool {
+    let (set, key) = (set.to_lowercase(), key.to_lowercase());
+    key == set || key.starts_with(&format!("{}.", set)) || key.starts_with(&format!("{}[", set))
+}
+
+/// True if the TOML `file` sets `key`, e.g. `waves.list[0].delay`, or an array
+/// or value that holds it. Tables are merged with other layers, anything else
+/// replaces what they set.
+fn defines(file: &Path, key: &str) -> bool {
+    let root = match fs::read_to_string(file).ok().and_then(|contents| contents.parse::<toml::Value>().ok()) {
+        Some(root) => root,
+        None => return false,
+    };
+
+    let mut value = &root;
+    for name in key.split('.').map(|part| part.split('[').next().unwrap_or_default()) {
+        value = match value.as_table().and_then(|table| table.get(name)) {
+            Some(value) => value,
+            None => return false,
+        };
+        if !value.is_table() {
+            return true;
+        }
+    }
+
+    true
+}
+
+/// The `settings` directory of the working directory, or else the one next to
+/// the executable, or else the one of the source tree the game was built from.
+fn settings_directory() -> PathBuf {
+    let candidates = [
+        Some(PathBuf::from("settings")),
+        env::current_exe().ok().and_then(|exe| Some(exe.parent()?.join("settings"))),
+        Some(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("settings")),
+    ];
+
+    candidates.iter()
+        .flatten()
+        .find(|directory| directory.is_dir())
+        .cloned()
+        .unwrap_or_else(|| PathBuf::from("settings"))
+}
+
+/// Per-user directory for settings that override those of the repository.
+pub fn user_directory() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|directory| directory.join("rusty-jam"))
+}
+
+impl Settings {
+    pub fn load(sources: &SettingsSources) -> Result<Self, ConfigError> {
+        sources.merge()?.try_into()
+    }
+
+}
 impl Settings {
     pub fn get_materials_path(&self) -> PathBuf {
         PathBuf::from(&self.data_dir).join("textures")
@@ -120,3 +484,92 @@ impl CameraSettings {
         Vector3::new(tuple.0, tuple.1, tuple.2)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard_input::Action;
+    use std::fs;
+
+    #[test]
+    fn later_layers_override_earlier_ones() {
+        let directory = tempfile::tempdir().unwrap();
+        let user_file = directory.path().join("settings.toml");
+        fs::write(&user_file, "[player.speed]\nrun = 7.0\njump = 2.0\n").unwrap();
+
+        let mut sources = SettingsSources {
+            user_file: Some(user_file.clone()),
+            ..SettingsSources::repository()
+        };
+        let settings = Settings::load(&sources).unwrap();
+        assert_eq!(settings.player.speed.run, 7.0);
+        assert_eq!(settings.player.speed.jump, 2.0);
+
+        sources.overrides.push(("player.speed.run".to_owned(), "9".to_owned()));
+        let settings = Settings::load(&sources).unwrap();
+        assert_eq!(settings.player.speed.run, 9.0);
+        assert_eq!(settings.player.speed.jump, 2.0);
+    }
+
+    #[test]
+    fn origin_is_the_last_layer_setting_the_key() {
+        let directory = tempfile::tempdir().unwrap();
+        let user_file = directory.path().join("settings.toml");
+        fs::write(&user_file, "[player.speed]\nrun = 7.0\n\n[[waves.list]]\ndelay = 1.0\nbots = {}\n").unwrap();
+
+        let mut sources = SettingsSources {
+            user_file: Some(user_file.clone()),
+            ..SettingsSources::repository()
+        };
+        sources.overrides.push(("player.health".to_owned(), "{}".to_owned()));
+
+        assert_eq!(sources.origin_of("player.speed.run"), user_file.display().to_string());
+        assert_eq!(sources.origin_of("waves.list[0].bots.nobody"), user_file.display().to_string());
+        assert_eq!(sources.origin_of("player.health.max"), "--set player.health");
+        assert_eq!(sources.origin_of("player.speed.jump"),
+                   sources.directory.join("player.toml").display().to_string());
+        assert_eq!(SettingsSources { directory: PathBuf::from("no such directory"), ..sources }
+                       .origin_of("keymap.bindings.Jump"),
+                   "built-in keymap.toml");
+    }
+
+    #[test]
+    fn defaults_are_used_without_the_directory() {
+        let sources = SettingsSources {
+            directory: PathBuf::from("no such directory"),
+            ..SettingsSources::repository()
+        };
+
+        assert!(Settings::load(&sources).is_ok());
+    }
+
+    #[test]
+    fn entries_deleted_from_the_directory_stay_deleted() {
+        let directory = tempfile::tempdir().unwrap();
+        let keymap = FILES.iter().find(|(name, _)| *name == "keymap").unwrap().1;
+        let without_jump = keymap.lines()
+            .filter(|line| !line.trim_start().starts_with("Jump"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        fs::write(directory.path().join("keymap.toml"), without_jump).unwrap();
+
+        let sources = SettingsSources {
+            directory: directory.path().to_owned(),
+            ..SettingsSources::repository()
+        };
+        let settings = Settings::load(&sources).unwrap();
+
+        assert!(!settings.keymap.bindings.contains_key(&Action::Jump));
+        assert!(settings.keymap.bindings.contains_key(&Action::Forward));
+        // The files the directory lacks still come from the defaults.
+        assert_eq!(settings.player.speed.run, Settings::load(&SettingsSources::repository()).unwrap().player.speed.run);
+    }
+
+    #[test]
+    fn effective_settings_are_toml() {
+        let effective = SettingsSources::repository().effective().unwrap();
+        let value = effective.parse::<toml::Value>().unwrap();
+
+        assert!(value.get("player").and_then(|player| player.get("speed")).is_some());
+    }
+}
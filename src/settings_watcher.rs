@@ -0,0 +1,117 @@
+use crate::{
+    settings::{Settings, SettingsSources},
+    validation::validate,
+    error::{GameError, GameResult},
+};
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Watches the settings files by polling their modification times.
+pub struct SettingsWatcher {
+    sources: SettingsSources,
+    modified: HashMap<PathBuf, SystemTime>,
+    last_poll: Instant,
+}
+
+impl SettingsWatcher {
+    pub fn new(sources: SettingsSources) -> Self {
+        let modified = snapshot(&sources);
+
+        Self {
+            sources,
+            modified,
+            last_poll: Instant::now(),
+        }
+    }
+
+    /// Returns true if a file was created, removed or modified since the last change was seen.
+    pub fn poll(&mut self) -> bool {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll = Instant::now();
+
+        let modified = snapshot(&self.sources);
+        if modified == self.modified {
+            return false;
+        }
+
+        self.modified = modified;
+        true
+    }
+
+    /// Parses and validates the settings again. Models, animations, scenes, behaviors
+    /// and waves are used at startup and only change after a restart, but a problem
+    /// in any of them still rejects the whole reload.
+    pub fn reload(&self) -> GameResult<Settings> {
+        let settings = Settings::load(&self.sources)?;
+
+        let problems = validate(&settings, &self.sources);
+        if !problems.is_empty() {
+            return Err(GameError::Validation(problems));
+        }
+
+        Ok(settings)
+    }
+}
+
+fn snapshot(sources: &SettingsSources) -> HashMap<PathBuf, SystemTime> {
+    sources.files()
+        .into_iter()
+        .filter_map(|file| {
+            let modified = fs::metadata(&file).and_then(|metadata| metadata.modified()).ok()?;
+            Some((file, modified))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Polls right away instead of waiting for `POLL_INTERVAL`.
+    fn poll(watcher: &mut SettingsWatcher) -> bool {
+        watcher.last_poll = Instant::now() - POLL_INTERVAL;
+        watcher.poll()
+    }
+
+    #[test]
+    fn edits_are_detected_and_invalid_ones_rejected() {
+        let directory = tempfile::tempdir().unwrap();
+        let user_file = directory.path().join("settings.toml");
+
+        let mut watcher = SettingsWatcher::new(SettingsSources {
+            user_file: Some(user_file.clone()),
+            ..SettingsSources::repository()
+        });
+        assert!(!poll(&mut watcher));
+
+        fs::write(&user_file, "[player.speed]\nrun = 9.0\n").unwrap();
+        assert!(poll(&mut watcher));
+        assert!(!poll(&mut watcher));
+        let settings = watcher.reload().unwrap();
+        assert_eq!(settings.player.speed.run, 9.0);
+
+        // Removed and written again, so that the change is seen even with coarse timestamps.
+        fs::remove_file(&user_file).unwrap();
+        assert!(poll(&mut watcher));
+        fs::write(&user_file, "[player.speed]\nrun = -1.0\n").unwrap();
+        assert!(poll(&mut watcher));
+
+        match watcher.reload() {
+            Err(GameError::Validation(problems)) => {
+                assert_eq!(problems.len(), 1, "{:#?}", problems);
+                assert_eq!(problems[0].key, "player.speed.run");
+                assert_eq!(problems[0].origin, user_file.display().to_string());
+            }
+            other => panic!("invalid settings were accepted: {:?}", other.map(|settings| settings.player.speed.run)),
+        }
+        assert_eq!(settings.player.speed.run, 9.0);
+    }
+}
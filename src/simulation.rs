@@ -0,0 +1,480 @@
+use crate::{
+    GameTime, FIXED_TIMESTEP,
+    settings::Settings,
+    message::{Message, MessageHandler},
+    player::Player,
+    bot::Bot,
+    navmesh::NavMesh,
+    perception::Target,
+    steering::{self, Neighbour},
+    waves::{WaveSpawner, Difficulty},
+    assets::AssetRegistry,
+    resource_helper::{CharacterResources, bare_bot_archetypes, load_bot_archetypes, request_asset, timed},
+    error::{GameError, GameResult},
+    keyboard_input::InputEvent,
+    input_recording::{InputRecorder, InputReplay},
+};
+use rg3d::{
+    core::{
+        algebra::{Vector2, Vector3},
+        futures::future::join,
+        pool::{Handle, Pool},
+    },
+    engine::resource_manager::ResourceManager,
+    resource::model::Model,
+    scene::{Scene, node::Node},
+    utils::log::{Log, MessageKind},
+};
+use std::{
+    collections::HashMap,
+    sync::mpsc::{self, Receiver, Sender},
+};
+use rand::{SeedableRng, rngs::StdRng};
+
+/// Gameplay state that does not depend on a window or a renderer.
+pub struct Simulation {
+    pub player: Player,
+    pub bots: Pool<Bot>,
+    /// Every random decision in gameplay must be drawn from this generator,
+    /// so that runs with the same seed and inputs are identical.
+    pub rng: StdRng,
+    /// Number of fixed ticks simulated so far.
+    pub tick: u64,
+    pub recorder: Option<InputRecorder>,
+    /// While set, live input is ignored and recorded input is fed instead.
+    pub replay: Option<InputReplay>,
+    pub navmesh: NavMesh,
+    pub spawner: WaveSpawner,
+    /// Distance from the player at which engaged bots surround it, from `bot.steering`.
+    surround_radius: f32,
+    /// Keyed by archetype name.
+    bot_resources: HashMap<String, CharacterResources>,
+    events_receiver: Receiver<Message>,
+    events_sender: Sender<Message>,
+}
+
+/// What a `Simulation` instantiates its characters from.
+pub struct SimulationResources {
+    pub player: CharacterResources,
+    /// Missing along with the models of bare characters.
+    pub weapon: Option<Model>,
+    /// Keyed by archetype name.
+    pub bots: HashMap<String, CharacterResources>,
+}
+
+impl SimulationResources {
+    pub async fn load(resource_manager: &ResourceManager, settings: &Settings) -> GameResult<Self> {
+        let assets = AssetRegistry::new(settings);
+        let player_asset = assets.character("player")?;
+
+        let ((player, weapon), bots) = join(
+            join(
+                timed("player", CharacterResources::load(resource_manager, settings, &player_asset)),
+                timed("weapon", request_asset(resource_manager, settings, &assets.weapon())),
+            ),
+            timed("bots", load_bot_archetypes(resource_manager, settings)),
+        ).await;
+
+        Ok(Self {
+            player: player?,
+            weapon: Some(weapon?),
+            bots: bots?,
+        })
+    }
+
+    /// Resources without any model, see `CharacterResources::bare`.
+    pub fn bare(settings: &Settings) -> GameResult<Self> {
+        Ok(Self {
+            player: CharacterResources::bare(&AssetRegistry::new(settings).character("player")?),
+            weapon: None,
+            bots: bare_bot_archetypes(settings)?,
+        })
+    }
+}
+
+/// Loads the level named by `settings.level`, to be instantiated into the scene of a `Simulation`.
+pub async fn load_level(resource_manager: &ResourceManager, settings: &Settings) -> GameResult<Model> {
+    let level_asset = AssetRegistry::new(settings).scene(&settings.level)?;
+    timed(&level_asset.name, request_asset(resource_manager, settings, &level_asset.file)).await
+}
+
+impl Simulation {
+    /// Loads all resources and instantiates the level and the player into `scene`.
+    pub async fn load(scene: &mut Scene,
+                      resource_manager: &ResourceManager,
+                      settings: &Settings,
+                      seed: u64) -> GameResult<Self> {
+        let (level, resources) = timed("all resources", join(
+            load_level(resource_manager, settings),
+            SimulationResources::load(resource_manager, settings),
+        )).await;
+
+        let level = level?.instantiate_geometry(scene);
+
+        Self::new(scene, level, settings, resources?, seed)
+    }
+
+    /// Instantiates the player into `scene`, which already holds `level`.
+    pub fn new(scene: &mut Scene,
+               level: Handle<Node>,
+               settings: &Settings,
+               resources: SimulationResources,
+               seed: u64) -> GameResult<Self> {
+        let (sender, receiver) = mpsc::channel();
+
+        Log::writeln(MessageKind::Information, format!("Simulation seed: {}", seed));
+
+        let level_asset = AssetRegistry::new(settings).scene(&settings.level)?;
+
+        let spawner = WaveSpawner::new(scene, level, settings.waves.clone(), sender.clone());
+        Log::writeln(MessageKind::Information,
+                     format!("Found {} bot spawn points", spawner.spawn_point_count()));
+
+        // Built before any character exists, so that only the level is walkable.
+        let navmesh = NavMesh::build(&scene.physics, &level_asset.settings.navmesh);
+        Log::writeln(MessageKind::Information,
+                     format!("Navmesh built: {} walkable cells", navmesh.walkable_count()));
+
+        let player = Player::new(scene, settings, &resources.player, resources.weapon, sender.clone())?;
+
+        Ok(Self {
+            player,
+            bots: Default::default(),
+            rng: StdRng::seed_from_u64(seed),
+            tick: 0,
+            recorder: None,
+            replay: None,
+            navmesh,
+            spawner,
+            surround_radius: settings.bot.steering.surround_radius,
+            bot_resources: resources.bots,
+            events_sender: sender,
+            events_receiver: receiver,
+        })
+    }
+
+    /// Advances gameplay by one fixed tick and returns the messages dispatched
+    /// during it, so subscribers outside of the simulation can see them too.
+    pub fn update(&mut self, scene: &mut Scene, settings: &Settings, time: GameTime) -> Vec<Message> {
+        if let Some(replay) = &mut self.replay {
+            for event in replay.events_for(self.tick) {
+                self.player.process_input_event(&event);
+            }
+        }
+
+        let struck = self.player.update(scene, time);
+        let damage = self.player.weapon.damage();
+
+        for bot in self.bots.iter_mut() {
+            if struck.contains(&bot.body.collider) {
+                bot.apply_damage(damage, time);
+            }
+        }
+
+        let target = if self.player.is_dead() {
+            None
+        } else {
+            Some(Target {
+                position: self.player.position(&scene.physics),
+                collider: self.player.body.collider,
+            })
+        };
+
+        self.assign_surround_slots(target);
+
+        let neighbours = self.bots
+            .iter()
+            .map(|bot| Neighbour::of(&bot.body, &scene.physics))
+            .collect::<Vec<_>>();
+
+        for bot in self.bots.iter_mut() {
+            if let Some(damage) = bot.update(scene, &self.navmesh, time, target, &neighbours) {
+                self.player.apply_damage(damage, time);
+            }
+        }
+
+        if let Some(order) = self.spawner.update(time, self.bots.alive_count(), &mut self.rng) {
+            for (archetype, position) in order.bots {
+                if let Err(error) = self.create_bot(scene, settings, &archetype, position, order.difficulty) {
+                    Log::writeln(MessageKind::Error, format!("Failed to spawn a bot: {}", error));
+                }
+            }
+        }
+
+        self.tick += 1;
+        if let Some(recorder) = &mut self.recorder {
+            recorder.flush();
+        }
+
+        self.dispatch_messages(scene)
+    }
+
+    /// Makes the player and the bots adopt the current settings.
+    pub fn apply_settings(&mut self, scene: &mut Scene, settings: &Settings) {
+        self.player.apply_settings(scene, settings);
+        self.surround_radius = settings.bot.steering.surround_radius;
+        for bot in self.bots.iter_mut() {
+            bot.apply_settings(settings);
+        }
+    }
+
+    /// Spreads the bots engaged with the target around it, so that they don't stack up.
+    fn assign_surround_slots(&mut self, target: Option<Target>) {
+        let target = match target {
+            Some(target) => target,
+            None => {
+                for bot in self.bots.iter_mut() {
+                    bot.ai.blackboard.slot = None;
+                }
+                return;
+            }
+        };
+
+        let engaged = self.bots
+            .iter()
+            .filter(|bot| bot.ai.blackboard.engaged)
+            .map(|bot| bot.ai.blackboard.position)
+            .collect::<Vec<_>>();
+
+        let mut slots = steering::surround_slots(target.position, &engaged, self.surround_radius).into_iter();
+
+        for bot in self.bots.iter_mut() {
+            bot.ai.blackboard.slot = if bot.ai.blackboard.engaged {
+                slots.next()
+            } else {
+                None
+            };
+        }
+    }
+
+    pub fn process_input_event(&mut self, event: &InputEvent) {
+        if self.replay.is_some() {
+            return;
+        }
+
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(self.tick, event);
+        }
+
+        self.player.process_input_event(event);
+    }
+
+    fn dispatch_messages(&mut self, scene: &mut Scene) -> Vec<Message> {
+        let messages = self.events_receiver.try_iter().collect::<Vec<_>>();
+
+        for message in messages.iter() {
+            self.player.handle_message(message);
+            for bot in self.bots.iter_mut() {
+                bot.handle_message(message);
+            }
+
+            self.handle_message(scene, message);
+        }
+
+        messages
+    }
+
+    fn handle_message(&mut self, scene: &mut Scene, message: &Message) {
+        match *message {
+            Message::DamageDealt { target, amount, health } => {
+                Log::writeln(MessageKind::Information,
+                             format!("{:?} took {} damage, {} health left", target, amount, health));
+            }
+            Message::Healed { .. } => (),
+            Message::CharacterDied { body } => {
+                if body == self.player.body.body {
+                    Log::writeln(MessageKind::Information, "Player died".to_owned());
+                    return;
+                }
+
+                let dead = self.bots
+                    .pair_iter()
+                    .find(|(_, bot)| bot.body.body == body)
+                    .map(|(handle, _)| handle);
+
+                if let Some(handle) = dead {
+                    let mut bot = self.bots.free(handle);
+                    bot.clean_up(scene);
+                }
+            }
+            Message::BotSpawned { .. } => (),
+            Message::AttackStarted { .. } => (),
+            Message::Footstep { .. } => (),
+            Message::WaveStarted { wave, bots } => {
+                Log::writeln(MessageKind::Information, format!("Wave {} started: {} bots", wave, bots));
+            }
+            Message::WaveCleared { wave } => {
+                Log::writeln(MessageKind::Information, format!("Wave {} cleared", wave));
+            }
+            Message::LevelFinished => {
+                Log::writeln(MessageKind::Information, "Level finished".to_owned());
+            }
+        }
+    }
+
+    /// Spawns a bot of the archetype named `archetype` under `bot.archetypes`.
+    pub fn create_bot(&mut self,
+                      scene: &mut Scene,
+                      settings: &Settings,
+                      archetype: &str,
+                      position: Vector3<f32>,
+                      difficulty: Difficulty) -> GameResult<()> {
+        let resources = self.bot_resources
+            .get(archetype)
+            .ok_or_else(|| GameError::Undefined {
+                key: "bot.archetypes".to_owned(),
+                name: archetype.to_owned(),
+            })?;
+
+        let bot = Bot::new(scene,
+                           settings,
+                           resources,
+                           self.events_sender.clone(),
+                           position,
+                           difficulty,
+                           self.player.body.body)?;
+
+        let body = bot.body.body;
+        self.bots.spawn(bot);
+
+        let _ = self.events_sender.send(Message::BotSpawned { body });
+
+        Ok(())
+    }
+}
+
+/// Runs a `Simulation` on its own scene, without `GameEngine`. Nothing is
+/// rendered, so the characters are bare and only the level is loaded.
+pub struct Headless {
+    pub scene: Scene,
+    pub simulation: Simulation,
+    pub time: GameTime,
+    /// Fixed for the whole run; headless runs don't reload settings.
+    pub settings: Settings,
+}
+
+impl Headless {
+    pub async fn new(settings: Settings, seed: u64) -> GameResult<Self> {
+        let mut scene = Scene::new();
+        let level = load_level(&ResourceManager::new(), &settings).await?.instantiate_geometry(&mut scene);
+
+        Self::with_level(scene, level, settings, seed)
+    }
+
+    /// Runs on `scene`, which already holds `level`, e.g. one built by a test.
+    pub fn with_level(mut scene: Scene, level: Handle<Node>, settings: Settings, seed: u64) -> GameResult<Self> {
+        let resources = SimulationResources::bare(&settings)?;
+        let simulation = Simulation::new(&mut scene, level, &settings, resources, seed)?;
+
+        Ok(Self {
+            scene,
+            simulation,
+            time: GameTime::new(),
+            settings,
+        })
+    }
+
+    /// Advances the simulation and the physics by `FIXED_TIMESTEP`.
+    pub fn step(&mut self) -> Vec<Message> {
+        self.time.elapsed += FIXED_TIMESTEP as f64;
+
+        let messages = self.simulation.update(&mut self.scene, &self.settings, self.time);
+        self.scene.update(Vector2::new(1.0, 1.0), FIXED_TIMESTEP);
+
+        messages
+    }
+
+    pub fn run(&mut self, ticks: u64) {
+        for _ in 0..ticks {
+            self.step();
+        }
+
+        let simulation = &self.simulation;
+        Log::writeln(MessageKind::Information,
+                     format!("Simulated {} ticks ({:.1}s): player health {}, player position {:?}, {} bots alive",
+                             ticks,
+                             self.time.elapsed,
+                             simulation.player.health,
+                             simulation.player.position(&self.scene.physics),
+                             simulation.bots.alive_count()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::SettingsSources;
+    use rg3d::{
+        physics::{
+            dynamics::{RigidBodyBuilder, RigidBodyType},
+            geometry::ColliderBuilder,
+        },
+        scene::{base::BaseBuilder, transform::TransformBuilder},
+    };
+
+    /// Flat ground around the origin, with two bot spawn markers under the level node.
+    fn arena(settings: &Settings) -> (Scene, Handle<Node>) {
+        let mut scene = Scene::new();
+
+        let ground = scene.physics.add_body(RigidBodyBuilder::new(RigidBodyType::Static).build());
+        scene.physics.add_collider(
+            ColliderBuilder::cuboid(30.0, 0.5, 30.0)
+                .translation(Vector3::new(0.0, -0.5, 0.0))
+                .build(),
+            &ground,
+        );
+
+        let markers = [Vector3::new(6.0, 0.5, 6.0), Vector3::new(-6.0, 0.5, 6.0)]
+            .iter()
+            .map(|&position| {
+                BaseBuilder::new()
+                    .with_name(&format!("{}{}", settings.waves.marker_prefix, position.x))
+                    .with_local_transform(TransformBuilder::new().with_local_position(position).build())
+                    .build(&mut scene.graph)
+            })
+            .collect::<Vec<_>>();
+        let level = BaseBuilder::new()
+            .with_children(&markers)
+            .build(&mut scene.graph);
+
+        (scene, level)
+    }
+
+    /// Runs the arena without input and returns every message dispatched,
+    /// by tick, along with where every bot is at the end.
+    fn run(seed: u64, ticks: u64) -> (Vec<(u64, String)>, Vec<Vector3<f32>>) {
+        let settings = Settings::load(&SettingsSources::repository()).unwrap();
+        let (scene, level) = arena(&settings);
+        let mut headless = Headless::with_level(scene, level, settings, seed).unwrap();
+        assert_eq!(headless.simulation.spawner.spawn_point_count(), 2);
+
+        let mut messages = Vec::new();
+        for tick in 0..ticks {
+            for message in headless.step() {
+                messages.push((tick, format!("{:?}", message)));
+            }
+        }
+
+        let positions = headless.simulation.bots
+            .iter()
+            .map(|bot| bot.position(&headless.scene.physics))
+            .collect();
+        (messages, positions)
+    }
+
+    #[test]
+    fn runs_with_the_same_seed_are_identical() {
+        let (messages, positions) = run(42, 300);
+
+        // The first wave of settings/waves.toml has two bots and comes after 2 seconds.
+        let expected = (2.0 / FIXED_TIMESTEP) as u64;
+        let first_wave = messages.iter()
+            .find(|(_, message)| message.starts_with("WaveStarted"))
+            .expect("the first wave did not start");
+        assert!(first_wave.0 + 1 >= expected && first_wave.0 <= expected + 1, "{:?}", first_wave);
+        assert_eq!(first_wave.1, "WaveStarted { wave: 1, bots: 2 }");
+        assert_eq!(positions.len(), 2);
+
+        assert_eq!(run(42, 300), (messages, positions));
+    }
+}
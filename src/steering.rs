@@ -0,0 +1,272 @@
+use crate::character_body::CharacterBody;
+use rg3d::{
+    engine::{ColliderHandle, RigidBodyHandle},
+    scene::{Scene, physics::{Physics, RayCastOptions}},
+    core::{
+        algebra::Vector3,
+        math::ray::Ray,
+    },
+};
+use std::{cmp::Ordering, f32::consts::PI};
+
+/// A snapshot of another agent, taken before any of them moves during a tick.
+#[derive(Debug, Clone, Copy)]
+pub struct Neighbour {
+    pub body: RigidBodyHandle,
+    pub position: Vector3<f32>,
+    pub velocity: Vector3<f32>,
+}
+
+impl Neighbour {
+    pub fn of(body: &CharacterBody, physics: &Physics) -> Self {
+        let rigid_body = physics.bodies.get(&body.body).unwrap();
+
+        Self {
+            body: body.body,
+            position: rigid_body.position().translation.vector,
+            velocity: *rigid_body.linvel(),
+        }
+    }
+}
+
+fn horizontal(vector: Vector3<f32>) -> Vector3<f32> {
+    Vector3::new(vector.x, 0.0, vector.z)
+}
+
+/// Scales `vector` down to `length` if it is longer.
+pub fn truncate(vector: Vector3<f32>, length: f32) -> Vector3<f32> {
+    let norm = vector.norm();
+    if norm > length {
+        vector.scale(length / norm)
+    } else {
+        vector
+    }
+}
+
+/// Velocity towards `target` at `speed`, slowing down linearly within `slowing_radius`.
+pub fn arrive(position: Vector3<f32>, target: Vector3<f32>, speed: f32, slowing_radius: f32) -> Vector3<f32> {
+    let offset = horizontal(target - position);
+    let distance = offset.norm();
+
+    if distance <= f32::EPSILON {
+        return Vector3::zeros();
+    }
+
+    let speed = if distance < slowing_radius {
+        speed * distance / slowing_radius
+    } else {
+        speed
+    };
+
+    offset.scale(speed / distance)
+}
+
+/// Pushes away from every neighbour closer than `radius`, the harder the closer it is.
+pub fn separation<'a>(position: Vector3<f32>,
+                      neighbours: impl Iterator<Item = &'a Neighbour>,
+                      radius: f32) -> Vector3<f32> {
+    let mut force = Vector3::zeros();
+
+    for neighbour in neighbours {
+        let offset = horizontal(position - neighbour.position);
+        let distance = offset.norm();
+
+        if distance >= radius {
+            continue;
+        }
+
+        // Agents standing exactly on top of each other still have to split up somehow.
+        let direction = offset.try_normalize(f32::EPSILON).unwrap_or_else(Vector3::x);
+        force += direction.scale(1.0 - distance / radius);
+    }
+
+    force
+}
+
+/// Unit vector towards the centre of the neighbours.
+pub fn cohesion<'a>(position: Vector3<f32>, neighbours: impl Iterator<Item = &'a Neighbour>) -> Vector3<f32> {
+    let (sum, count) = neighbours.fold((Vector3::zeros(), 0), |(sum, count), neighbour| {
+        (sum + neighbour.position, count + 1)
+    });
+
+    if count == 0 {
+        return Vector3::zeros();
+    }
+
+    horizontal(sum.scale(1.0 / count as f32) - position)
+        .try_normalize(f32::EPSILON)
+        .unwrap_or_else(Vector3::zeros)
+}
+
+/// Unit vector along the average heading of the neighbours.
+pub fn alignment<'a>(neighbours: impl Iterator<Item = &'a Neighbour>) -> Vector3<f32> {
+    let sum = neighbours.fold(Vector3::zeros(), |sum, neighbour| sum + neighbour.velocity);
+
+    horizontal(sum)
+        .try_normalize(f32::EPSILON)
+        .unwrap_or_else(Vector3::zeros)
+}
+
+/// Feeler ray cast along `velocity`; returns a push off whatever it hits,
+/// stronger the closer the hit is.
+pub fn avoid_obstacles(scene: &Scene,
+                       position: Vector3<f32>,
+                       velocity: Vector3<f32>,
+                       ignored: &[ColliderHandle],
+                       distance: f32) -> Vector3<f32> {
+    let direction = match horizontal(velocity).try_normalize(f32::EPSILON) {
+        Some(direction) => direction,
+        None => return Vector3::zeros(),
+    };
+
+    // Cast at knee height, so that the ground itself is not an obstacle.
+    let origin = position + Vector3::new(0.0, 0.5, 0.0);
+
+    let mut intersections = Vec::new();
+    scene.physics.cast_ray(
+        RayCastOptions {
+            ray: Ray::new(origin, direction.scale(distance)),
+            max_len: distance,
+            groups: Default::default(),
+            sort_results: true,
+        },
+        &mut intersections,
+    );
+
+    let hit = intersections.iter()
+        .find(|intersection| !ignored.contains(&intersection.collider));
+
+    match hit {
+        Some(hit) => {
+            let proximity = 1.0 - (hit.position.coords - origin).norm() / distance;
+            horizontal(hit.normal)
+                .try_normalize(f32::EPSILON)
+                .unwrap_or_else(|| -direction)
+                .scale(proximity.max(0.0))
+        }
+        None => Vector3::zeros(),
+    }
+}
+
+/// Spreads `positions` evenly on a circle of `radius` around `center`, keeping
+/// their angular order so that no two agents have to cross paths.
+/// Returns one slot per position, in the same order.
+pub fn surround_slots(center: Vector3<f32>, positions: &[Vector3<f32>], radius: f32) -> Vec<Vector3<f32>> {
+    let angle_of = |position: &Vector3<f32>| (position.z - center.z).atan2(position.x - center.x);
+
+    let mut order = (0..positions.len()).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| {
+        angle_of(&positions[a])
+            .partial_cmp(&angle_of(&positions[b]))
+            .unwrap_or(Ordering::Equal)
+    });
+
+    let base = order.first().map_or(0.0, |&first| angle_of(&positions[first]));
+    let step = 2.0 * PI / positions.len().max(1) as f32;
+
+    let mut slots = vec![center; positions.len()];
+    for (i, &index) in order.iter().enumerate() {
+        let angle = base + step * i as f32;
+        slots[index] = center + Vector3::new(angle.cos(), 0.0, angle.sin()).scale(radius);
+    }
+
+    slots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Vector3<f32>, b: Vector3<f32>) -> bool {
+        (a - b).norm() < 1e-4
+    }
+
+    fn neighbour(x: f32, z: f32) -> Neighbour {
+        Neighbour {
+            body: RigidBodyHandle::from_raw_parts(0, 0),
+            position: Vector3::new(x, 0.0, z),
+            velocity: Vector3::zeros(),
+        }
+    }
+
+    #[test]
+    fn arrive_slows_down_near_the_target() {
+        let position = Vector3::zeros();
+
+        assert!(close(arrive(position, Vector3::new(10.0, 5.0, 0.0), 4.0, 2.0), Vector3::new(4.0, 0.0, 0.0)));
+        assert!(close(arrive(position, Vector3::new(0.0, 0.0, 1.0), 4.0, 2.0), Vector3::new(0.0, 0.0, 2.0)));
+        assert!(close(arrive(position, Vector3::new(0.0, 0.0, 1.0), 4.0, 0.0), Vector3::new(0.0, 0.0, 4.0)));
+        assert_eq!(arrive(position, Vector3::new(0.0, 3.0, 0.0), 4.0, 2.0), Vector3::zeros());
+    }
+
+    #[test]
+    fn separation_pushes_away_from_close_neighbours_only() {
+        let position = Vector3::zeros();
+        let neighbours = [neighbour(0.5, 0.0), neighbour(0.0, -1.5), neighbour(3.0, 0.0)];
+
+        let force = separation(position, neighbours.iter(), 2.0);
+        assert!(close(force, Vector3::new(-0.75, 0.0, 0.25)), "{:?}", force);
+
+        let on_top = separation(position, [neighbour(0.0, 0.0)].iter(), 2.0);
+        assert!(close(on_top, Vector3::new(1.0, 0.0, 0.0)), "{:?}", on_top);
+
+        assert_eq!(separation(position, std::iter::empty(), 2.0), Vector3::zeros());
+    }
+
+    #[test]
+    fn cohesion_points_to_the_centre_of_the_neighbours() {
+        let position = Vector3::zeros();
+        let neighbours = [neighbour(2.0, 0.0), neighbour(2.0, 2.0)];
+
+        let force = cohesion(position, neighbours.iter());
+        assert!(close(force, Vector3::new(2.0, 0.0, 1.0).normalize()), "{:?}", force);
+
+        // Height does not pull agents up or down.
+        let above = Neighbour { position: Vector3::new(0.0, 5.0, 3.0), ..neighbour(0.0, 0.0) };
+        assert!(close(cohesion(position, [above].iter()), Vector3::new(0.0, 0.0, 1.0)));
+
+        assert_eq!(cohesion(position, [neighbour(0.0, 0.0)].iter()), Vector3::zeros());
+        assert_eq!(cohesion(position, std::iter::empty()), Vector3::zeros());
+    }
+
+    #[test]
+    fn alignment_follows_the_average_heading() {
+        let moving = |x: f32, y: f32, z: f32| Neighbour { velocity: Vector3::new(x, y, z), ..neighbour(0.0, 0.0) };
+
+        let force = alignment([moving(1.0, 0.0, 0.0), moving(0.0, 3.0, 1.0)].iter());
+        assert!(close(force, Vector3::new(1.0, 0.0, 1.0).normalize()), "{:?}", force);
+
+        // Faster neighbours weigh more.
+        let force = alignment([moving(3.0, 0.0, 0.0), moving(0.0, 0.0, 1.0)].iter());
+        assert!(close(force, Vector3::new(3.0, 0.0, 1.0).normalize()), "{:?}", force);
+
+        assert_eq!(alignment([moving(1.0, 0.0, 0.0), moving(-1.0, 0.0, 0.0)].iter()), Vector3::zeros());
+        assert_eq!(alignment([moving(0.0, 0.0, 0.0)].iter()), Vector3::zeros());
+        assert_eq!(alignment(std::iter::empty()), Vector3::zeros());
+    }
+
+    #[test]
+    fn surround_slots_are_spread_evenly_in_angular_order() {
+        let center = Vector3::new(1.0, 0.0, 1.0);
+        let positions = [
+            Vector3::new(6.0, 0.0, 1.0),
+            Vector3::new(1.2, 0.0, 3.0),
+            Vector3::new(1.0, 0.0, 2.0),
+            Vector3::new(-4.0, 0.0, 1.0),
+        ];
+
+        let slots = surround_slots(center, &positions, 2.0);
+        assert_eq!(slots.len(), positions.len());
+        for slot in slots.iter() {
+            assert!(((slot - center).norm() - 2.0).abs() < 1e-4);
+        }
+
+        // The first slot is towards the position at the lowest angle, each next one a quarter turn further.
+        assert!(close(slots[0], Vector3::new(3.0, 0.0, 1.0)), "{:?}", slots);
+        assert!(close(slots[1], Vector3::new(1.0, 0.0, 3.0)), "{:?}", slots);
+        assert!(close(slots[2], Vector3::new(-1.0, 0.0, 1.0)), "{:?}", slots);
+        assert!(close(slots[3], Vector3::new(1.0, 0.0, -1.0)), "{:?}", slots);
+
+        assert!(surround_slots(center, &[], 2.0).is_empty());
+    }
+}
@@ -0,0 +1,358 @@
+use crate::{
+    assets::{AssetFile, AssetKind, AssetRegistry},
+    animation_rule::Rule,
+    behavior_tree::BehaviorTree,
+    character_animation::CharacterAnimationInput,
+    keyboard_input::Action,
+    resource_helper::request_asset,
+    settings::{Settings, SettingsSources, AnimationSettings, CharacterModel, CharacterSpeedSettings, HealthSettings},
+};
+use rg3d::engine::resource_manager::ResourceManager;
+use std::{
+    collections::HashMap,
+    fmt,
+};
+
+/// Something wrong with the value at `key`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    /// Layer the value at `key` comes from: a settings file, a `JAM_*` variable or `--set`.
+    pub origin: String,
+    pub key: String,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: `{}`: {}", self.origin, self.key, self.message)
+    }
+}
+
+struct Problems<'a>(Vec<Problem>, &'a SettingsSources);
+
+impl<'a> Problems<'a> {
+    fn add<K: Into<String>, M: Into<String>>(&mut self, key: K, message: M) {
+        let key = key.into();
+        self.0.push(Problem {
+            origin: self.1.origin_of(&key),
+            key,
+            message: message.into(),
+        });
+    }
+
+    fn positive(&mut self, key: String, value: f32) {
+        if !(value.is_finite() && value > 0.0) {
+            self.add(key, format!("must be a positive number, got {}", value));
+        }
+    }
+
+    fn non_negative(&mut self, key: String, value: f32) {
+        if !(value.is_finite() && value >= 0.0) {
+            self.add(key, format!("must be a non-negative number, got {}", value));
+        }
+    }
+
+    fn file_exists(&mut self, file: &AssetFile) {
+        if !file.path.is_file() {
+            self.add(file.key.clone(), format!("file {} does not exist", file.path.display()));
+        }
+    }
+
+    fn rule(&mut self, key: String, rule: &str) {
+        if let Err(error) = Rule::parse(rule, CharacterAnimationInput::FLAGS) {
+            self.add(key, error.to_string());
+        }
+    }
+}
+
+/// Checks everything that can be checked without loading assets.
+/// Returns every problem found rather than stopping at the first one.
+/// `sources` are those `settings` were loaded from, to tell where bad values come from.
+pub fn validate(settings: &Settings, sources: &SettingsSources) -> Vec<Problem> {
+    let mut problems = Problems(Vec::new(), sources);
+    let assets = AssetRegistry::new(settings);
+
+    if let Err(error) = assets.scene(&settings.level) {
+        problems.add("level", error.to_string());
+    }
+    for name in sorted(&settings.scenes) {
+        let scene = assets.scene(name).unwrap();
+        problems.file_exists(&scene.file);
+
+        let navmesh = &scene.settings.navmesh;
+        let key = format!("scenes.{}.navmesh", name);
+        problems.positive(format!("{}.cell_size", key), navmesh.cell_size);
+        problems.non_negative(format!("{}.max_step", key), navmesh.max_step);
+        problems.non_negative(format!("{}.agent_radius", key), navmesh.agent_radius);
+        if navmesh.min.0 >= navmesh.max.0 || navmesh.min.1 >= navmesh.max.1 {
+            problems.add(key.clone(), "`min` must be below `max`");
+        }
+        if navmesh.height_range.0 >= navmesh.height_range.1 {
+            problems.add(format!("{}.height_range", key), "the lower bound must come first");
+        }
+    }
+
+    for action in Action::ALL {
+        if settings.keymap.bindings.get(action).map_or(true, Vec::is_empty) {
+            problems.add(format!("keymap.bindings.{:?}", action), "nothing is bound to this action");
+        }
+    }
+    problems.non_negative("keymap.double_tap_interval".to_owned(), settings.keymap.double_tap_interval);
+
+    let gamepad = &settings.keymap.gamepad;
+    if !(0.0..1.0).contains(&gamepad.deadzone) {
+        problems.add("keymap.gamepad.deadzone", format!("must be at least 0 and below 1, got {}", gamepad.deadzone));
+    }
+    if !(gamepad.outer_deadzone > gamepad.deadzone && gamepad.outer_deadzone <= 1.0) {
+        problems.add("keymap.gamepad.outer_deadzone", "must be above `deadzone` and at most 1");
+    }
+    if !(gamepad.trigger_threshold > 0.0 && gamepad.trigger_threshold <= 1.0) {
+        problems.add("keymap.gamepad.trigger_threshold",
+                     format!("must be above 0 and at most 1, got {}", gamepad.trigger_threshold));
+    }
+    problems.non_negative("keymap.gamepad.look_speed".to_owned(), gamepad.look_speed);
+
+    let mut characters = vec!["player"];
+    characters.extend(assets.bot_archetypes());
+    for name in characters {
+        // Undefined animation sets are reported along with the archetype.
+        if let Ok(character) = assets.character(name) {
+            problems.file_exists(&character.model_file);
+        }
+    }
+
+    check_model(&mut problems, "models.player", &settings.models.player);
+    check_speed(&mut problems, "player.speed", &settings.player.speed);
+    check_health(&mut problems, "player.health", &settings.player.health);
+    problems.non_negative("player.footstep_interval".to_owned(), settings.player.footstep_interval);
+    let camera = &settings.player.camera;
+    let offsets = [("offset", camera.offset), ("hinge_offset", camera.hinge_offset)];
+    for (name, offset) in offsets.iter() {
+        if ![offset.0, offset.1, offset.2].iter().all(|value| value.is_finite()) {
+            problems.add(format!("player.camera.{}", name), "must be finite");
+        }
+    }
+    problems.positive("player.camera.sensitivity".to_owned(), camera.sensitivity);
+    problems.non_negative("player.camera.smoothing".to_owned(), camera.smoothing);
+    problems.non_negative("player.camera.acceleration".to_owned(), camera.acceleration);
+    let (lowest, highest) = camera.pitch_limits;
+    if !(-90.0 <= lowest && lowest < highest && highest <= 90.0) {
+        problems.add("player.camera.pitch_limits", "must be ordered and within -90 and 90 degrees");
+    }
+
+    problems.file_exists(&assets.weapon());
+    problems.positive("models.weapon.scale".to_owned(), settings.models.weapon.scale);
+    let weapon = &settings.weapon;
+    problems.non_negative("weapon.damage".to_owned(), weapon.damage);
+    problems.positive("weapon.reach".to_owned(), weapon.reach);
+    problems.non_negative("weapon.cooldown".to_owned(), weapon.cooldown);
+    problems.positive("weapon.swing_duration".to_owned(), weapon.swing_duration);
+    if weapon.active_window.0 > weapon.active_window.1 {
+        problems.add("weapon.active_window", "the start must not come after the end");
+    }
+    if weapon.arc_rays == 0 {
+        problems.add("weapon.arc_rays", "at least one ray is needed");
+    }
+
+    check_animations(&mut problems, &assets, "animations.player", &settings.animations.player);
+    for name in sorted(&settings.animations.bots) {
+        let key = format!("animations.bots.{}", name);
+        check_animations(&mut problems, &assets, &key, &settings.animations.bots[name]);
+    }
+
+    for name in sorted(&settings.bot.behaviors) {
+        if let Err(reason) = BehaviorTree::new(&settings.bot.behaviors[name]) {
+            problems.add(format!("bot.behaviors.{}", name), reason);
+        }
+    }
+
+    for name in sorted(&settings.bot.archetypes) {
+        let archetype = &settings.bot.archetypes[name];
+        let key = format!("bot.archetypes.{}", name);
+
+        check_model(&mut problems, &format!("{}.model", key), &archetype.model);
+        check_speed(&mut problems, &format!("{}.speed", key), &archetype.speed);
+        check_health(&mut problems, &format!("{}.health", key), &archetype.health);
+        problems.non_negative(format!("{}.ai.damage", key), archetype.ai.damage);
+        problems.positive(format!("{}.ai.attack_range", key), archetype.ai.attack_range);
+        problems.positive(format!("{}.perception.fov", key), archetype.perception.fov);
+        problems.non_negative(format!("{}.perception.view_distance", key), archetype.perception.view_distance);
+
+        if !settings.animations.bots.contains_key(&archetype.animations) {
+            problems.add(format!("{}.animations", key),
+                         format!("\"{}\" is not defined under `animations.bots`", archetype.animations));
+        }
+        if !settings.bot.behaviors.contains_key(&archetype.behavior) {
+            problems.add(format!("{}.behavior", key),
+                         format!("\"{}\" is not defined under `bot.behaviors`", archetype.behavior));
+        }
+    }
+
+    let waves = &settings.waves;
+    problems.non_negative("waves.spread".to_owned(), waves.spread);
+    let (min, max) = waves.fallback_area;
+    if !(min.is_finite() && max.is_finite() && min < max) {
+        problems.add("waves.fallback_area", format!("must be two finite numbers, lowest first, got [{}, {}]", min, max));
+    }
+    if !waves.fallback_height.is_finite() {
+        problems.add("waves.fallback_height", "must be finite");
+    }
+    if waves.list.is_empty() {
+        problems.add("waves.list", "at least one wave is needed");
+    }
+    for (i, wave) in settings.waves.list.iter().enumerate() {
+        let key = format!("waves.list[{}]", i);
+        problems.non_negative(format!("{}.delay", key), wave.delay);
+        for name in wave.bots.keys() {
+            if !settings.bot.archetypes.contains_key(name) {
+                problems.add(format!("{}.bots.{}", key, name),
+                             format!("\"{}\" is not defined under `bot.archetypes`", name));
+            }
+        }
+    }
+
+    problems.0
+}
+
+fn check_model(problems: &mut Problems, key: &str, model: &CharacterModel) {
+    problems.positive(format!("{}.scale", key), model.scale);
+    problems.positive(format!("{}.size[0]", key), model.size.0);
+    problems.positive(format!("{}.size[1]", key), model.size.1);
+}
+
+fn check_speed(problems: &mut Problems, key: &str, speed: &CharacterSpeedSettings) {
+    problems.non_negative(format!("{}.run", key), speed.run);
+    problems.non_negative(format!("{}.jump", key), speed.jump);
+}
+
+fn check_health(problems: &mut Problems, key: &str, health: &HealthSettings) {
+    problems.positive(format!("{}.max", key), health.max);
+    problems.non_negative(format!("{}.invulnerability", key), health.invulnerability);
+    problems.non_negative(format!("{}.regeneration", key), health.regeneration);
+    problems.non_negative(format!("{}.regeneration_delay", key), health.regeneration_delay);
+}
+
+fn check_animations(problems: &mut Problems,
+                    assets: &AssetRegistry,
+                    key: &str,
+                    animations: &AnimationSettings) {
+    if !animations.states.contains_key(&animations.entry) {
+        problems.add(format!("{}.entry", key), format!("state \"{}\" is not defined", animations.entry));
+    }
+
+    for name in sorted(&animations.states) {
+        let state = &animations.states[name];
+        let state_key = format!("{}.states.{}", key, name);
+
+        problems.file_exists(&assets.file(AssetKind::Animation, format!("{}.file", state_key), &state.file));
+        if let Some(rewind) = &state.rewind {
+            problems.rule(format!("{}.rewind", state_key), rewind);
+        }
+    }
+
+    for (i, transition) in animations.transitions.iter().enumerate() {
+        let transition_key = format!("{}.transitions[{}]", key, i);
+
+        for (field, state) in [("from", &transition.from), ("to", &transition.to)].iter() {
+            if !animations.states.contains_key(*state) {
+                problems.add(format!("{}.{}", transition_key, field), format!("state \"{}\" is not defined", state));
+            }
+        }
+        problems.non_negative(format!("{}.blend", transition_key), transition.blend);
+        problems.rule(format!("{}.rule", transition_key), &transition.rule);
+    }
+}
+
+fn sorted<V>(map: &HashMap<String, V>) -> Vec<&str> {
+    let mut keys = map.keys().map(String::as_str).collect::<Vec<_>>();
+    keys.sort_unstable();
+    keys
+}
+
+/// Loads every character model and checks that the nodes the settings refer to exist.
+/// Missing files are left to `validate`; models that fail to load are reported.
+pub async fn validate_models(resource_manager: &ResourceManager,
+                             settings: &Settings,
+                             sources: &SettingsSources) -> Vec<Problem> {
+    let mut problems = Problems(Vec::new(), sources);
+    let assets = AssetRegistry::new(settings);
+
+    let mut characters = vec!["player"];
+    characters.extend(assets.bot_archetypes());
+
+    for name in characters {
+        let character = match assets.character(name) {
+            Ok(character) => character,
+            // Reported by `validate`.
+            Err(_) => continue,
+        };
+
+        if !character.model_file.path.is_file() {
+            continue;
+        }
+
+        let model = match request_asset(resource_manager, settings, &character.model_file).await {
+            Ok(model) => model,
+            Err(error) => {
+                problems.add(character.model_file.key.clone(), error.to_string());
+                continue;
+            }
+        };
+
+        let mut nodes = vec![(format!("{}.spine", character.model_key), &character.model.spine)];
+        if name == "player" {
+            nodes.push(("player.hand_node".to_owned(), &settings.player.hand_node));
+        }
+
+        let model = model.data_ref();
+        let graph = &model.get_scene().graph;
+        for (key, node) in nodes {
+            if graph.find_by_name_from_root(node).is_none() {
+                problems.add(key, format!("node \"{}\" not found in {}", node, character.model_file.path.display()));
+            }
+        }
+    }
+
+    problems.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::SettingsSources;
+
+    #[test]
+    fn repository_settings_are_valid() {
+        let sources = SettingsSources::repository();
+        let settings = Settings::load(&sources).unwrap();
+        let problems = validate(&settings, &sources);
+
+        assert!(problems.is_empty(), "{:#?}", problems);
+    }
+
+    #[test]
+    fn every_problem_is_reported_with_its_file() {
+        let mut sources = SettingsSources::repository();
+        sources.overrides.push(("player.health.max".to_owned(), "-5".to_owned()));
+        let mut settings = Settings::load(&sources).unwrap();
+        settings.keymap.bindings.clear();
+        settings.player.speed.run = -1.0;
+        settings.waves.list[0].bots.insert("nobody".to_owned(), 1);
+        settings.waves.spread = f32::NAN;
+        settings.waves.fallback_area = (5.0, -5.0);
+
+        let problems = validate(&settings, &sources);
+        let find = |key: &str| problems.iter()
+            .find(|problem| problem.key == key)
+            .unwrap_or_else(|| panic!("no problem at {}: {:#?}", key, problems));
+
+        let file = |name: &str| sources.directory.join(name).display().to_string();
+        assert_eq!(find("keymap.bindings.Attack").origin, file("keymap.toml"));
+        assert_eq!(find("player.speed.run").origin, file("player.toml"));
+        assert_eq!(find("waves.list[0].bots.nobody").origin, file("waves.toml"));
+        assert_eq!(find("player.health.max").origin, "--set player.health.max");
+        assert_eq!(find("waves.spread").origin, file("waves.toml"));
+        assert_eq!(find("waves.fallback_area").origin, file("waves.toml"));
+        assert_eq!(problems.iter().filter(|problem| problem.key.starts_with("keymap")).count(), Action::ALL.len());
+    }
+}
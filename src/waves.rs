@@ -0,0 +1,187 @@
+use crate::{
+    GameTime,
+    message::Message,
+    settings::{WavesSettings, DifficultySettings},
+};
+use rg3d::{
+    scene::{Scene, node::Node},
+    core::{algebra::Vector3, pool::Handle},
+};
+use rand::Rng;
+use std::sync::mpsc::Sender;
+
+/// Multipliers applied to the settings of the bots of a wave.
+#[derive(Debug, Clone, Copy)]
+pub struct Difficulty {
+    pub health: f32,
+    pub damage: f32,
+    pub speed: f32,
+}
+
+impl Default for Difficulty {
+    fn default() -> Self {
+        Self {
+            health: 1.0,
+            damage: 1.0,
+            speed: 1.0,
+        }
+    }
+}
+
+impl Difficulty {
+    /// Difficulty of the wave at `index`, counting from zero.
+    pub fn for_wave(scaling: &DifficultySettings, index: usize) -> Self {
+        let index = index as f32;
+
+        Self {
+            health: 1.0 + scaling.health * index,
+            damage: 1.0 + scaling.damage * index,
+            speed: 1.0 + scaling.speed * index,
+        }
+    }
+}
+
+/// Bots the simulation has to spawn for a wave that has just started.
+#[derive(Debug, Clone)]
+pub struct SpawnOrder {
+    /// Archetype name and position of every bot.
+    pub bots: Vec<(String, Vector3<f32>)>,
+    pub difficulty: Difficulty,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Waiting { wave: usize, remaining: f32 },
+    InProgress { wave: usize },
+    Finished,
+}
+
+pub struct WaveSpawner {
+    settings: WavesSettings,
+    spawn_points: Vec<Vector3<f32>>,
+    state: State,
+    sender: Sender<Message>,
+}
+
+impl WaveSpawner {
+    /// Spawn points are the nodes under `level` whose names start with `settings.marker_prefix`.
+    pub fn new(scene: &mut Scene, level: Handle<Node>, settings: WavesSettings, sender: Sender<Message>) -> Self {
+        scene.graph.update_hierarchical_data();
+
+        let mut spawn_points = Vec::new();
+        let mut stack = vec![level];
+        while let Some(handle) = stack.pop() {
+            let node = &scene.graph[handle];
+            if node.name().starts_with(&settings.marker_prefix) {
+                spawn_points.push(node.global_position());
+            }
+            stack.extend_from_slice(node.children());
+        }
+
+        let state = match settings.list.first() {
+            Some(first) => State::Waiting { wave: 0, remaining: first.delay },
+            None => State::Finished,
+        };
+
+        Self {
+            settings,
+            spawn_points,
+            state,
+            sender,
+        }
+    }
+
+    pub fn spawn_point_count(&self) -> usize {
+        self.spawn_points.len()
+    }
+
+    /// `alive` is the number of bots left from the current wave.
+    pub fn update<R: Rng>(&mut self, time: GameTime, alive: usize, rng: &mut R) -> Option<SpawnOrder> {
+        match self.state {
+            State::Waiting { wave, remaining } => {
+                let remaining = remaining - time.delta;
+                if remaining > 0.0 {
+                    self.state = State::Waiting { wave, remaining };
+                    return None;
+                }
+
+                self.state = State::InProgress { wave };
+
+                let mut bots = Vec::new();
+                for (archetype, &count) in self.settings.list[wave].bots.iter() {
+                    for _ in 0..count {
+                        bots.push((archetype.clone(), self.spawn_position(rng)));
+                    }
+                }
+
+                let _ = self.sender.send(Message::WaveStarted { wave: wave as u32 + 1, bots: bots.len() as u32 });
+
+                Some(SpawnOrder {
+                    bots,
+                    difficulty: Difficulty::for_wave(&self.settings.scaling, wave),
+                })
+            }
+            State::InProgress { wave } => {
+                if alive == 0 {
+                    let _ = self.sender.send(Message::WaveCleared { wave: wave as u32 + 1 });
+
+                    self.state = match self.settings.list.get(wave + 1) {
+                        Some(next) => State::Waiting { wave: wave + 1, remaining: next.delay },
+                        None => {
+                            let _ = self.sender.send(Message::LevelFinished);
+                            State::Finished
+                        }
+                    };
+                }
+                None
+            }
+            State::Finished => None,
+        }
+    }
+
+    fn spawn_position<R: Rng>(&self, rng: &mut R) -> Vector3<f32> {
+        if self.spawn_points.is_empty() {
+            // The level has no markers, fall back to a random point in the configured area.
+            let (min, max) = self.settings.fallback_area;
+            return Vector3::new(uniform(rng, min, max), self.settings.fallback_height, uniform(rng, min, max));
+        }
+
+        let spread = self.settings.spread;
+        let point = self.spawn_points[rng.gen_range(0..self.spawn_points.len())];
+        point + Vector3::new(uniform(rng, -spread, spread), 0.0, uniform(rng, -spread, spread))
+    }
+}
+
+/// A random number between `min` and `max`. Settings are validated, but one
+/// reloaded with a bad range must not crash the game, so an empty or infinite
+/// range gives `min` if it is finite and 0 otherwise.
+fn uniform<R: Rng>(rng: &mut R, min: f32, max: f32) -> f32 {
+    if min.is_finite() && max.is_finite() && min < max {
+        rng.gen_range(min..max)
+    } else if min.is_finite() {
+        min
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{SeedableRng, rngs::StdRng};
+
+    #[test]
+    fn bad_ranges_do_not_panic() {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for &(min, max) in &[(5.0, -5.0), (1.0, 1.0), (-1.0, f32::NAN), (f32::NAN, 1.0), (f32::NEG_INFINITY, 0.0)] {
+            let value = uniform(&mut rng, min, max);
+            assert!(value.is_finite(), "{} for [{}, {}]", value, min, max);
+        }
+
+        for _ in 0..100 {
+            let value = uniform(&mut rng, -2.0, 3.0);
+            assert!((-2.0..3.0).contains(&value));
+        }
+    }
+}
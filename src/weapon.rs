@@ -0,0 +1,254 @@
+use crate::{
+    GameTime,
+    settings::{Settings, WeaponSettings},
+};
+use rg3d::{
+    engine::ColliderHandle,
+    resource::model::Model,
+    scene::{Scene, node::Node, physics::RayCastOptions},
+    core::{
+        algebra::{Vector3, UnitQuaternion},
+        math::ray::Ray,
+        pool::Handle,
+    },
+};
+use std::collections::HashSet;
+
+struct Swing {
+    start_time: f64,
+    struck: HashSet<ColliderHandle>,
+}
+
+pub struct Weapon {
+    /// Only the look of the weapon, hits are detected from the pivot of its owner.
+    /// `Handle::NONE` for a weapon without a model.
+    pub model: Handle<Node>,
+    pub hand: Handle<Node>,
+    pub stats: WeaponSettings,
+    swing: Option<Swing>,
+    last_swing_time: Option<f64>,
+}
+
+impl Weapon {
+    pub fn new(
+        scene: &mut Scene,
+        settings: &Settings,
+        model: Option<Model>,
+        hand: Handle<Node>,
+    ) -> Self {
+        let model = match model {
+            Some(model) => {
+                let model = model.instantiate_geometry(scene);
+
+                let scale = settings.models.weapon.scale;
+                scene.graph[model]
+                    .local_transform_mut()
+                    .set_scale(Vector3::new(scale, scale, scale));
+
+                scene.graph.link_nodes(model, hand);
+                model
+            }
+            None => Handle::NONE,
+        };
+
+        Self {
+            model,
+            hand,
+            stats: settings.weapon,
+            swing: None,
+            last_swing_time: None,
+        }
+    }
+
+    pub fn damage(&self) -> f32 {
+        self.stats.damage
+    }
+
+    pub fn is_ready(&self, time: GameTime) -> bool {
+        match self.last_swing_time {
+            Some(last) => time.elapsed - last >= self.stats.cooldown as f64,
+            None => true,
+        }
+    }
+
+    pub fn is_swinging(&self, time: GameTime) -> bool {
+        match &self.swing {
+            Some(swing) => time.elapsed - swing.start_time < self.stats.swing_duration as f64,
+            None => false,
+        }
+    }
+
+    /// Returns false if the weapon is still swinging or cooling down.
+    pub fn start_swing(&mut self, time: GameTime) -> bool {
+        if self.is_swinging(time) || !self.is_ready(time) {
+            return false;
+        }
+
+        self.swing = Some(Swing {
+            start_time: time.elapsed,
+            struck: Default::default(),
+        });
+        self.last_swing_time = Some(time.elapsed);
+
+        true
+    }
+
+    /// Sweeps the hit arc in front of `pivot` while the swing is in its active window
+    /// and returns colliders struck for the first time during the current swing.
+    pub fn update(&mut self,
+                  scene: &Scene,
+                  time: GameTime,
+                  pivot: Handle<Node>,
+                  owner: ColliderHandle) -> Vec<ColliderHandle> {
+        if !self.is_striking(time) {
+            return Vec::new();
+        }
+
+        let pivot = &scene.graph[pivot];
+        let origin = pivot.global_position() + Vector3::new(0.0, self.stats.height, 0.0);
+        let look = pivot.look_vector();
+        let forward = Vector3::new(look.x, 0.0, look.z)
+            .try_normalize(f32::EPSILON)
+            .unwrap_or_else(Vector3::z);
+
+        let half_arc = self.stats.arc.to_radians() / 2.0;
+        let rays = self.stats.arc_rays.max(1);
+        let mut intersections = Vec::new();
+        let mut hits = Vec::new();
+
+        for i in 0..rays {
+            let angle = if rays == 1 {
+                0.0
+            } else {
+                -half_arc + 2.0 * half_arc * i as f32 / (rays - 1) as f32
+            };
+            let direction = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), angle)
+                .transform_vector(&forward);
+
+            intersections.clear();
+            scene.physics.cast_ray(
+                RayCastOptions {
+                    ray: Ray::new(origin, direction.scale(self.stats.reach)),
+                    max_len: self.stats.reach,
+                    groups: Default::default(),
+                    sort_results: true,
+                },
+                &mut intersections,
+            );
+
+            // Walls, the level and other characters shield whatever is behind them.
+            hits.extend(first_hit(intersections.iter().map(|intersection| intersection.collider), owner));
+        }
+
+        self.strike(time, hits)
+    }
+
+    /// Whether the current swing is in its active window, in which hits count.
+    /// Forgets the swing once it is over.
+    fn is_striking(&mut self, time: GameTime) -> bool {
+        let swing_time = match &self.swing {
+            Some(swing) => (time.elapsed - swing.start_time) as f32,
+            None => return false,
+        };
+
+        if swing_time > self.stats.swing_duration {
+            self.swing = None;
+        }
+
+        let (window_start, window_end) = self.stats.active_window;
+        swing_time >= window_start && swing_time <= window_end
+    }
+
+    /// Returns those of `hits` that are struck for the first time during the
+    /// current swing, or nothing outside of its active window.
+    fn strike(&mut self, time: GameTime, hits: Vec<ColliderHandle>) -> Vec<ColliderHandle> {
+        if !self.is_striking(time) {
+            return Vec::new();
+        }
+
+        match &mut self.swing {
+            Some(swing) => hits.into_iter().filter(|collider| swing.struck.insert(*collider)).collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// The first of `colliders`, sorted by distance along a ray, that is not `owner`.
+fn first_hit<C: PartialEq>(colliders: impl IntoIterator<Item = C>, owner: C) -> Option<C> {
+    colliders.into_iter().find(|collider| *collider != owner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn weapon() -> Weapon {
+        Weapon {
+            model: Handle::NONE,
+            hand: Handle::NONE,
+            stats: WeaponSettings {
+                damage: 10.0,
+                reach: 2.0,
+                cooldown: 0.5,
+                swing_duration: 0.6,
+                active_window: (0.2, 0.4),
+                arc: 90.0,
+                arc_rays: 5,
+                height: 1.0,
+            },
+            swing: None,
+            last_swing_time: None,
+        }
+    }
+
+    fn at(elapsed: f64) -> GameTime {
+        GameTime { elapsed, ..GameTime::new() }
+    }
+
+    fn collider(index: u32) -> ColliderHandle {
+        ColliderHandle::from_raw_parts(index, 0)
+    }
+
+    #[test]
+    fn hits_count_only_in_the_active_window() {
+        let mut weapon = weapon();
+        assert!(weapon.strike(at(1.0), vec![collider(1)]).is_empty());
+
+        assert!(weapon.start_swing(at(1.0)));
+        assert!(weapon.strike(at(1.1), vec![collider(1)]).is_empty());
+        assert_eq!(weapon.strike(at(1.3), vec![collider(1)]), vec![collider(1)]);
+        assert!(weapon.strike(at(1.5), vec![collider(2)]).is_empty());
+    }
+
+    #[test]
+    fn targets_are_struck_once_per_swing() {
+        let mut weapon = weapon();
+
+        assert!(weapon.start_swing(at(1.0)));
+        assert_eq!(weapon.strike(at(1.25), vec![collider(1)]), vec![collider(1)]);
+        assert_eq!(weapon.strike(at(1.3), vec![collider(1), collider(2)]), vec![collider(2)]);
+        assert!(weapon.strike(at(1.35), vec![collider(1), collider(2)]).is_empty());
+    }
+
+    #[test]
+    fn struck_targets_reset_on_the_next_swing() {
+        let mut weapon = weapon();
+
+        assert!(weapon.start_swing(at(1.0)));
+        assert_eq!(weapon.strike(at(1.3), vec![collider(1)]), vec![collider(1)]);
+        // Still swinging, then cooling down.
+        assert!(!weapon.start_swing(at(1.5)));
+
+        assert!(weapon.start_swing(at(2.0)));
+        assert_eq!(weapon.strike(at(2.3), vec![collider(1)]), vec![collider(1)]);
+    }
+
+    #[test]
+    fn ray_stops_at_the_first_collider() {
+        let (owner, wall, bot) = (0, 1, 2);
+
+        assert_eq!(first_hit(vec![owner, wall, bot], owner), Some(wall));
+        assert_eq!(first_hit(vec![owner, bot, wall], owner), Some(bot));
+        assert_eq!(first_hit(vec![owner], owner), None);
+    }
+}